@@ -16,7 +16,7 @@ index="benchmark.index"
 inputs=( \
     test_input/Zika_virus.fasta \
     test_input/Monkeypox_virus.fasta \
-    #test_input/Drosophila_melanogaster_chromosome_Y.fasta \
+    test_input/Drosophila_melanogaster_chromosome_Y.fasta \
     #test_input/Mycobacterium_tuberculosis_H37Rv.fasta \
     #test_input/Streptococcus_pneumoniae_strain_NCTC7465_chromosome_1.fasta 
     test_input/Chlamydia_pneumoniae_TW-183.fasta
@@ -34,6 +34,35 @@ for input in ${inputs[@]}; do
 
             echo ""
 
+            # LCP-accelerated search, on short and long queries, against standard-query above
+            command=(target/release/mssa build "$k" "$w" lexicographic "$input" "$index" lcp-query)
+            print_and_exec
+            get_index_size
+            command=(target/release/mssa benchmark 100000 0.9 25 "$index" lcp-query)
+            print_and_exec
+            command=(target/release/mssa benchmark 10000 0.9 1000 "$index" lcp-query)
+            print_and_exec
+            rm -r "$index"
+
+            command=(target/release/mssa build "$k" "$w" lexicographic "$input" "$index" standard-query)
+            print_and_exec
+            command=(target/release/mssa benchmark 10000 0.9 1000 "$index" standard-query)
+            print_and_exec
+            rm -r "$index"
+
+            echo ""
+
+            # FM-index, trading index size against locate time with the suffix array sample rate
+            for sample_rate in 1 4 16 64; do
+                command=(target/release/mssa build "$k" "$w" lexicographic "$input" "$index" fm-index-query -s "$sample_rate")
+                print_and_exec
+                command=(target/release/mssa benchmark 10000 0.9 25 "$index" fm-index-query)
+                print_and_exec
+                rm -r "$index"
+            done
+
+            echo ""
+
             command=(target/release/mssa build "$k" "$w" occurrence "$input" "$index" standard-query)
             print_and_exec
             get_index_size
@@ -9,6 +9,13 @@ pub struct Sequence {
     pub representation: Vec<u8>,
 }
 
+impl Sequence {
+    /// The record identifier, i.e. the description up to the first whitespace.
+    pub fn name(&self) -> &str {
+        self.description.split_whitespace().next().unwrap_or("")
+    }
+}
+
 pub fn read_sequences<P: AsRef<Path>>(path: P) -> Result<Vec<Sequence>> {
     Ok(fs::read_to_string(path)?
         .split('>')
@@ -23,13 +30,33 @@ pub fn read_sequences<P: AsRef<Path>>(path: P) -> Result<Vec<Sequence>> {
         .collect())
 }
 
-pub fn generate_sequences(reference: &[u8], num: usize, match_rate: f64, min_len: usize, max_len: usize) -> Vec<Sequence> {
+/// Generates `num` random queries, drawing matching queries from every reference
+/// in proportion to its length. A matching query never spans two references.
+pub fn generate_sequences(references: &[&[u8]], num: usize, match_rate: f64, min_len: usize, max_len: usize) -> Vec<Sequence> {
     //let mut rng = rang::rng();
+    let total_len: usize = references.iter().map(|r| r.len()).sum();
+    let random_reference = || {
+        let mut pos = rand::random_range(0..total_len);
+        for reference in references {
+            if pos < reference.len() {
+                return *reference;
+            }
+            pos -= reference.len();
+        }
+        unreachable!()
+    };
+
     let mut result = Vec::new();
-    for i in 0..num {
+    let mut i = 0;
+    while result.len() < num {
+        let reference = random_reference();
+        if reference.len() < min_len {
+            continue;
+        }
+
         let length = rand::random_range(min_len..=std::cmp::min(max_len, reference.len()));
         if rand::random_bool(match_rate) {
-            let start_pos = rand::random_range(0..reference.len() - length);
+            let start_pos = rand::random_range(0..=reference.len() - length);
             let r = &reference[start_pos..start_pos + length];
 
             result.push(Sequence {
@@ -47,6 +74,7 @@ pub fn generate_sequences(reference: &[u8], num: usize, match_rate: f64, min_len
                 representation,
             });
         }
+        i += 1;
     }
     result
 }
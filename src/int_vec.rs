@@ -1,15 +1,23 @@
 use core::fmt;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
 use bincode::{Decode, Encode};
 use bitvec::prelude::*;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Serialize, Deserialize, Encode, Decode, Hash)]
+/// Integers of `bits` bits, compared lexicographically like slices. IntVecs of different
+/// integer sizes are never equal.
+#[derive(Clone, Serialize, Deserialize, Encode, Decode)]
 pub struct IntVec {
     #[bincode(with_serde)]
     inner: BitVec<u8>,
     bits: usize,
+
+    /// The first `128 / bits` integers, with the first one in the most significant bits and
+    /// zeros past the end, so that comparing prefixes compares those integers lexicographically
+    prefix: u128,
 }
 
 pub struct IntVecIterator<'a> {
@@ -38,7 +46,7 @@ impl IntVec {
 
         let inner = bitvec![u8, Lsb0; 0; 0];
 
-        Self { inner, bits }
+        Self { inner, bits, prefix: 0 }
     }
 
     /// Create a new IntVec of `len` integers of a generic length `bits` bits.
@@ -53,7 +61,7 @@ impl IntVec {
         let bit_count = len.checked_mul(bits).expect("IntArray size too large");
         let inner = bitvec![u8, Lsb0; 0; bit_count];
 
-        Self { inner, bits }
+        Self { inner, bits, prefix: 0 }
     }
 
     pub fn from_iter<V: IntoIterator<Item = u8>>(bits: usize, iter: V) -> Self {
@@ -96,6 +104,7 @@ impl IntVec {
         let range = start..(start + self.bits);
 
         self.inner.get_mut(range)?.store_le(value);
+        self.set_prefix(index, value);
 
         Some(())
     }
@@ -107,22 +116,53 @@ impl IntVec {
             "Value too large to fit in integer of specified length",
         );
 
+        let index = self.len();
         let mut val_bits = bitvec![u8, Lsb0; 0; self.bits];
         val_bits.store_le(value);
         self.inner.extend_from_bitslice(&val_bits);
+        self.set_prefix(index, value);
+    }
+
+    /// Updates `prefix` after storing `value` at `index`.
+    fn set_prefix(&mut self, index: usize, value: u8) {
+        if index < 128 / self.bits {
+            let shift = 128 - (index + 1) * self.bits;
+            let mask = ((1u128 << self.bits) - 1) << shift;
+            self.prefix = self.prefix & !mask | (value as u128) << shift;
+        }
+    }
+
+    /// The `i`-th 64 bits of the packed integers, with the bits past the end cleared.
+    fn word(&self, i: usize) -> u64 {
+        let bytes = self.inner.as_raw_slice();
+        let mut word = [0; 8];
+        let chunk = &bytes[(i * 8).min(bytes.len())..((i + 1) * 8).min(bytes.len())];
+        word[..chunk.len()].copy_from_slice(chunk);
+
+        let word = u64::from_le_bytes(word);
+        match self.inner.len().saturating_sub(i * 64) {
+            end if end >= 64 => word,
+            end => word & ((1 << end) - 1),
+        }
     }
 
-    /// Returns the entire IntVec as a u128.
-    /// Panics if the IntVec is longer than 128 bits.
-    pub fn as_u128(&self, alphabet_size: usize) -> u128 {
-        let vals: Vec<u8> = self.iter().collect();
+    /// Returns the entire IntVec as a base-`alphabet_size` u128.
+    /// Returns None if the value doesn't fit in a u128.
+    pub fn as_u128(&self, alphabet_size: usize) -> Option<u128> {
+        // In base 2^bits, the value is the prefix if every integer is in it
+        let len_bits = self.inner.len();
+        if alphabet_size == 1 << self.bits && len_bits <= 128 {
+            return Some(if len_bits == 0 { 0 } else { self.prefix >> (128 - len_bits) });
+        }
 
         let mut result = 0u128;
-        for (i, &v) in vals.iter().rev().enumerate() {
-            result += ((alphabet_size as u128).pow(i as u32) * v as u128) as u128;
+        for v in self.iter() {
+            result = result
+                .checked_mul(alphabet_size as u128)?
+                .checked_add(v as u128)?;
         }
 
-        result
+        Some(result)
     }
 
     pub fn iter(&self) -> IntVecIterator {
@@ -147,12 +187,50 @@ impl<'a> IntoIterator for &'a IntVec {
 
 impl PartialEq for IntVec {
     fn eq(&self, other: &Self) -> bool {
-        self.iter().eq(other.iter())
+        self.bits == other.bits
+            && self.inner.len() == other.inner.len()
+            && self.prefix == other.prefix
+            && (0..self.inner.len().div_ceil(64)).all(|i| self.word(i) == other.word(i))
     }
 }
 
 impl Eq for IntVec {}
 
+impl Hash for IntVec {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits.hash(state);
+        self.inner.len().hash(state);
+        for i in 0..self.inner.len().div_ceil(64) {
+            self.word(i).hash(state);
+        }
+    }
+}
+
+impl Ord for IntVec {
+    /// Compares the integers lexicographically, by their prefixes and then 64 bits at a time.
+    /// Panics if the integer sizes differ.
+    fn cmp(&self, other: &Self) -> Ordering {
+        assert_eq!(self.bits, other.bits, "compared IntVecs of different integer sizes");
+        self.prefix.cmp(&other.prefix).then_with(|| {
+            // The lowest differing bit belongs to the first differing integer
+            let common_bits = self.inner.len().min(other.inner.len());
+            (0..common_bits.div_ceil(64))
+                .find_map(|i| {
+                    let diff = self.word(i) ^ other.word(i);
+                    let bit = i * 64 + diff.trailing_zeros() as usize;
+                    (diff != 0 && bit < common_bits).then_some(bit / self.bits)
+                })
+                .map_or_else(|| self.len().cmp(&other.len()), |index| self.get(index).cmp(&other.get(index)))
+        })
+    }
+}
+
+impl PartialOrd for IntVec {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl fmt::Debug for IntVec {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -166,6 +244,8 @@ impl fmt::Debug for IntVec {
 
 #[cfg(test)]
 mod tests {
+    use rand::Rng;
+
     use super::*;
 
     #[test]
@@ -196,4 +276,82 @@ mod tests {
         assert_eq!(int_array.len(), 8);
         assert_eq!(int_array.inner.len(), 8 * 6);
     }
+
+    fn random_int_vec(rng: &mut impl Rng, bits: usize, max_len: usize) -> IntVec {
+        // Few distinct values, so that long common prefixes are likely
+        let max = (1u16 << bits).min(3) as u8;
+        let len = rng.random_range(0..=max_len);
+        IntVec::from_iter(bits, (0..len).map(|_| rng.random_range(0..max)))
+    }
+
+    #[test]
+    fn order_matches_element_wise() {
+        let mut rng = rand::rng();
+        for bits in 1..=8 {
+            for _ in 0..20000 {
+                let x = random_int_vec(&mut rng, bits, 150);
+                let mut y = random_int_vec(&mut rng, bits, 150);
+                if rng.random_bool(0.5) {
+                    // Share a prefix with x, possibly all of it
+                    let shared = rng.random_range(0..=x.len());
+                    y = IntVec::from_iter(bits, x.iter().take(shared).chain(y.iter()));
+                }
+
+                assert_eq!(x.cmp(&y), x.iter().cmp(y.iter()), "{:?} {:?}", x, y);
+                assert_eq!(x == y, x.iter().eq(y.iter()));
+                assert_eq!(x.cmp(&x.clone()), Ordering::Equal);
+            }
+        }
+    }
+
+    #[test]
+    fn equal_int_vecs_hash_equal() {
+        let mut rng = rand::rng();
+        let hash = |x: &IntVec| {
+            let mut hasher = std::hash::DefaultHasher::new();
+            x.hash(&mut hasher);
+            hasher.finish()
+        };
+        for bits in 1..=8 {
+            for _ in 0..1000 {
+                let x = random_int_vec(&mut rng, bits, 100);
+                // Built differently, through set rather than push
+                let mut y = IntVec::new_zeros(bits, x.len());
+                for (i, value) in x.iter().enumerate() {
+                    y.set(i, value);
+                }
+                assert!(x == y);
+                assert_eq!(hash(&x), hash(&y));
+                assert_eq!(x.as_u128(1 << bits), y.as_u128(1 << bits));
+            }
+        }
+    }
+
+    #[test]
+    fn as_u128_matches_element_wise() {
+        let mut rng = rand::rng();
+        for bits in 1..=8 {
+            for _ in 0..1000 {
+                let x = random_int_vec(&mut rng, bits, 140 / bits);
+                for alphabet_size in [1 << bits, (1 << bits) + 1] {
+                    let expected = x.iter().try_fold(0u128, |acc, v| {
+                        acc.checked_mul(alphabet_size as u128)?.checked_add(v as u128)
+                    });
+                    assert_eq!(x.as_u128(alphabet_size), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn as_u128_overflow() {
+        let int_array = IntVec::from_iter(5, [3, 0, 19, 7]);
+        assert_eq!(int_array.as_u128(20), Some((3 * 20 * 20 + 19) * 20 + 7));
+
+        // 20^30 > 2^128
+        let int_array = IntVec::from_iter(5, std::iter::repeat_n(19, 30));
+        assert_eq!(int_array.as_u128(20), None);
+        let int_array = IntVec::from_iter(5, std::iter::repeat_n(19, 29));
+        assert_eq!(int_array.as_u128(20), Some(20u128.pow(29) - 1));
+    }
 }
@@ -1,8 +1,12 @@
 mod fasta;
 mod int_vec;
+#[cfg(test)]
 mod iter_order_by;
+mod packed;
+mod sais;
 mod suffix_array;
 mod transform;
+mod wavelet;
 
 use std::{fs::File, path::PathBuf, time::{Duration, Instant}};
 
@@ -12,10 +16,10 @@ use clap::{Parser, ValueEnum};
 
 use fasta::Sequence;
 use suffix_array::{
-    BloomFilterQuery, GroundTruthQuery, PWLLearnedQuery, Queryable, StandardQuery,
-    SuffixArray,
+    BloomFilterQuery, FmIndexQuery, GroundTruthQuery, LcpQuery, LocateMode, PWLLearnedQuery, QueryMode, Queryable,
+    StandardQuery, Strand, SuffixArray,
 };
-use transform::{Alphabet, KmerSequence, MinimizerOrder};
+use transform::{reverse_complement, Alphabet, AlphabetReduction, KmerSequence, MinimizerOrder, SyncmerParams};
 
 #[derive(Debug, Clone, ValueEnum)]
 enum QueryType {
@@ -23,6 +27,28 @@ enum QueryType {
     StandardQuery,
     BloomFilterQuery,
     PWLLearnedQuery,
+    LcpQuery,
+    FmIndexQuery,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum AlphabetKind {
+    /// Every byte that occurs in the input
+    Inferred,
+    /// A/C/G/T, ignoring case and masking any other bytes
+    Dna,
+    /// The 20 standard amino acids, ignoring case and masking any other bytes
+    Protein,
+    /// Amino acids reduced to the 10 groups of Murphy et al.
+    Murphy10,
+    /// Amino acids reduced to the 6 Dayhoff groups
+    Dayhoff6,
+}
+
+/// How super-k-mers are sampled from each record
+enum Sampling {
+    Minimizers { w: usize, order: MinimizerOrder, hash_seed: u64 },
+    Syncmers(SyncmerParams),
 }
 
 #[derive(Parser)]
@@ -42,11 +68,62 @@ enum Args {
         // PWLLearnedQuery options
         #[arg(short, long)]
         piecewise_linear_gamma: Option<f64>,
+
+        // FmIndexQuery options: keep the suffix array entry of every s-th super-k-mer. Larger
+        // rates give smaller indexes, but locating each candidate takes up to s - 1 more steps.
+        #[arg(short, long)]
+        sample_rate: Option<usize>,
+
+        // DNA mode: choose minimizers over canonical k-mers and search both strands
+        #[arg(short, long)]
+        canonical: bool,
+
+        #[arg(short, long, value_enum, default_value_t = AlphabetKind::Inferred)]
+        alphabet: AlphabetKind,
+
+        // Seed of the hash-based minimizer orders
+        #[arg(long, default_value_t = 0)]
+        hash_seed: u64,
+
+        // Sample syncmers with this s-mer length instead of minimizers (w and minimizer_order
+        // are then ignored)
+        #[arg(long)]
+        syncmer_s: Option<usize>,
+
+        // Positions of the smallest s-mer that make a k-mer a syncmer (default: closed syncmers)
+        #[arg(long, value_delimiter = ',', requires = "syncmer_s")]
+        syncmer_offsets: Vec<usize>,
+
+        // Also store the LCP array of each suffix array (not supported by FmIndexQuery)
+        #[arg(long)]
+        lcp: bool,
+
+        // Number of threads used for construction, or 0 for one per core. The index doesn't
+        // depend on it.
+        #[arg(short, long, default_value_t = 1)]
+        threads: usize,
     },
     Query {
         fasta_file: PathBuf,
         suffix_array_file: PathBuf,
         query_type: QueryType,
+
+        // Report every occurrence, or stop at the first one
+        #[arg(short, long, value_enum, default_value_t = LocateMode::All)]
+        locate: LocateMode,
+    },
+    Count {
+        fasta_file: PathBuf,
+        suffix_array_file: PathBuf,
+        query_type: QueryType,
+
+        // Verify each candidate against the reference instead of counting minimizer-space matches
+        #[arg(short, long)]
+        verified: bool,
+    },
+    Stats {
+        suffix_array_file: PathBuf,
+        query_type: QueryType,
     },
     Benchmark {
         num_queries: usize,
@@ -54,6 +131,10 @@ enum Args {
         max_query_length: usize,
         suffix_array_file: PathBuf,
         query_type: QueryType,
+
+        // Report every occurrence, or stop at the first one
+        #[arg(short, long, value_enum, default_value_t = LocateMode::All)]
+        locate: LocateMode,
     },
 }
 
@@ -73,12 +154,57 @@ fn main() -> Result<()> {
             query_type,
             bloom_filter_fpr,
             piecewise_linear_gamma,
+            sample_rate,
+            canonical,
+            alphabet,
+            hash_seed,
+            syncmer_s,
+            syncmer_offsets,
+            lcp,
+            threads,
         } => {
+            rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()?;
             let sequences = fasta::read_sequences(fasta_file)?;
-            if sequences.len() > 1 {
-                eprintln!("Expected only one sequence in FASTA file. Continuing with the first sequence...");
+            // All records share one alphabet so that queries are encoded the same way for each
+            let alphabet = match alphabet {
+                AlphabetKind::Inferred if canonical => {
+                    Alphabet::from_sequences(sequences.iter().map(|s| s.representation.as_slice()))
+                        .complement_closed()
+                }
+                AlphabetKind::Inferred => {
+                    Alphabet::from_sequences(sequences.iter().map(|s| s.representation.as_slice()))
+                }
+                AlphabetKind::Dna => Alphabet::dna(),
+                AlphabetKind::Protein | AlphabetKind::Murphy10 | AlphabetKind::Dayhoff6 if canonical => {
+                    return Err(anyhow!("Canonical k-mers are only supported for DNA"));
+                }
+                AlphabetKind::Protein => Alphabet::protein(None),
+                AlphabetKind::Murphy10 => Alphabet::protein(Some(AlphabetReduction::Murphy10)),
+                AlphabetKind::Dayhoff6 => Alphabet::protein(Some(AlphabetReduction::Dayhoff6)),
+            };
+            let sampling = match syncmer_s {
+                Some(s) if s == 0 || s > k => {
+                    return Err(anyhow!("Syncmer s-mer length must be between 1 and k"));
+                }
+                Some(s) if syncmer_offsets.iter().any(|&offset| offset > k - s) => {
+                    return Err(anyhow!("Syncmer offsets must be at most k - s = {}", k - s));
+                }
+                Some(s) if syncmer_offsets.is_empty() => Sampling::Syncmers(SyncmerParams::closed(k, s)),
+                Some(s) => Sampling::Syncmers(SyncmerParams { s, offsets: syncmer_offsets }),
+                None if canonical && !minimizer_order.supports_canonical() => {
+                    return Err(anyhow!("Minimizer order {:?} doesn't support canonical k-mers", minimizer_order));
+                }
+                None if minimizer_order.uses_hash() && !alphabet.kmers_fit_in_u128(k) => {
+                    return Err(anyhow!("k is too large to hash k-mers for an alphabet of size {}", alphabet.len()));
+                }
+                None => Sampling::Minimizers { w, order: minimizer_order, hash_seed },
+            };
+            if sample_rate.is_some() && !matches!(query_type, QueryType::FmIndexQuery) {
+                return Err(anyhow!("Only the FM-index query type samples the suffix array"));
+            }
+            if lcp && matches!(query_type, QueryType::FmIndexQuery) {
+                return Err(anyhow!("The FM-index query type doesn't store the suffix array, so it has no LCP array"));
             }
-            let sequence = sequences.into_iter().take(1).next().context("Expected one sequence in FASTA file")?;
             let suffix_array_file = &mut File::create(suffix_array_file)?;
 
             let before = Instant::now();
@@ -86,16 +212,22 @@ fn main() -> Result<()> {
 
             match query_type {
                 QueryType::GroundTruthQuery => {
-                    let mut suffix_arrays = Vec::new();
-                    let alphabet = Alphabet::from_bytes(&sequence.representation);
-                    let kmers = KmerSequence::from_bytes(&sequence.representation, k, alphabet);
+                    let suffix_arrays = build_suffix_arrays::<GroundTruthQuery>(&sequences, k, &sampling, &alphabet, canonical, lcp, ())?;
 
-                    let suffix_array =
-                        SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, minimizer_order, ());
+                    time_elapsed = before.elapsed();
+                    print_sampling_stats(&suffix_arrays);
 
-                    suffix_arrays.push(suffix_array);
+                    bincode::encode_into_std_write(
+                        suffix_arrays,
+                        suffix_array_file,
+                        bincode_config,
+                    )?;
+                }
+                QueryType::StandardQuery => {
+                    let suffix_arrays = build_suffix_arrays::<StandardQuery>(&sequences, k, &sampling, &alphabet, canonical, lcp, ())?;
 
                     time_elapsed = before.elapsed();
+                    print_sampling_stats(&suffix_arrays);
 
                     bincode::encode_into_std_write(
                         suffix_arrays,
@@ -103,16 +235,28 @@ fn main() -> Result<()> {
                         bincode_config,
                     )?;
                 }
-                QueryType::StandardQuery => {
-                    let mut suffix_arrays = Vec::new();
-                    let alphabet = Alphabet::from_bytes(&sequence.representation);
-                    let kmers = KmerSequence::from_bytes(&sequence.representation, k, alphabet);
+                QueryType::LcpQuery => {
+                    let suffix_arrays = build_suffix_arrays::<LcpQuery>(&sequences, k, &sampling, &alphabet, canonical, lcp, ())?;
 
-                    let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, minimizer_order, ());
+                    time_elapsed = before.elapsed();
+                    print_sampling_stats(&suffix_arrays);
 
-                    suffix_arrays.push(suffix_array);
+                    bincode::encode_into_std_write(
+                        suffix_arrays,
+                        suffix_array_file,
+                        bincode_config,
+                    )?;
+                }
+                QueryType::FmIndexQuery => {
+                    let sample_rate = sample_rate.context("Expected suffix array sample rate")?;
+                    if sample_rate == 0 {
+                        return Err(anyhow!("Suffix array sample rate must be positive"));
+                    }
+
+                    let suffix_arrays = build_suffix_arrays::<FmIndexQuery>(&sequences, k, &sampling, &alphabet, canonical, lcp, sample_rate)?;
 
                     time_elapsed = before.elapsed();
+                    print_sampling_stats(&suffix_arrays);
 
                     bincode::encode_into_std_write(
                         suffix_arrays,
@@ -121,21 +265,19 @@ fn main() -> Result<()> {
                     )?;
                 }
                 QueryType::BloomFilterQuery => {
-                    let mut suffix_arrays = Vec::new();
-                    let alphabet = Alphabet::from_bytes(&sequence.representation);
-                    let kmers = KmerSequence::from_bytes(&sequence.representation, k, alphabet);
-
-                    let suffix_array = SuffixArray::<BloomFilterQuery>::from_kmers(
-                        kmers,
-                        w,
-                        minimizer_order,
+                    let suffix_arrays = build_suffix_arrays::<BloomFilterQuery>(
+                        &sequences,
+                        k,
+                        &sampling,
+                        &alphabet,
+                        canonical,
+                        lcp,
                         bloom_filter_fpr
                             .context("Expected Bloom filter false positive rate")?,
-                    );
-
-                    suffix_arrays.push(suffix_array);
+                    )?;
 
                     time_elapsed = before.elapsed();
+                    print_sampling_stats(&suffix_arrays);
 
                     bincode::encode_into_std_write(
                         suffix_arrays,
@@ -144,21 +286,23 @@ fn main() -> Result<()> {
                     )?;
                 }
                 QueryType::PWLLearnedQuery => {
-                    let mut suffix_arrays = Vec::new();
-                        let alphabet = Alphabet::from_bytes(&sequence.representation);
-                        let kmers = KmerSequence::from_bytes(&sequence.representation, k, alphabet);
-
-                        let suffix_array = SuffixArray::<PWLLearnedQuery>::from_kmers(
-                            kmers,
-                            w,
-                            minimizer_order,
-                            piecewise_linear_gamma
-                                .context("Expected piecewise linear regression gamma factor")?,
-                        );
+                    if !alphabet.kmers_fit_in_u128(k) {
+                        return Err(anyhow!("k is too large to map k-mers to integers for an alphabet of size {}", alphabet.len()));
+                    }
 
-                        suffix_arrays.push(suffix_array);
+                    let suffix_arrays = build_suffix_arrays::<PWLLearnedQuery>(
+                        &sequences,
+                        k,
+                        &sampling,
+                        &alphabet,
+                        canonical,
+                        lcp,
+                        piecewise_linear_gamma
+                            .context("Expected piecewise linear regression gamma factor")?,
+                    )?;
 
                     time_elapsed = before.elapsed();
+                    print_sampling_stats(&suffix_arrays);
 
                     bincode::encode_into_std_write(
                         suffix_arrays,
@@ -174,6 +318,7 @@ fn main() -> Result<()> {
             fasta_file,
             suffix_array_file,
             query_type,
+            locate,
         } => {
             let sequences = fasta::read_sequences(fasta_file)?;
             let suffix_array_file = &mut File::open(suffix_array_file)?;
@@ -185,7 +330,7 @@ fn main() -> Result<()> {
                                 format!("Could not decode index with query type {:?}", query_type)
                             })?;
 
-                    let result = query(suffix_arrays, sequences);
+                    let result = query(suffix_arrays, sequences, locate);
                     // println!("{:?}", result.0);
                     println!("False positives: {:?}", result.1);
                 }
@@ -196,12 +341,42 @@ fn main() -> Result<()> {
                                 format!("Could not decode index with query type {:?}", query_type)
                             })?;
 
-                    let result = query(suffix_arrays, sequences);
+                    let result = query(suffix_arrays, sequences, locate);
+                    // println!("{:?}", result.0);
+                    println!("False positives: {:?}", result.1);
+                }
+                QueryType::LcpQuery => {
+                    let suffix_arrays: Vec<SuffixArray<LcpQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    let result = query(suffix_arrays, sequences, locate);
+                    // println!("{:?}", result.0);
+                    println!("False positives: {:?}", result.1);
+                }
+                QueryType::FmIndexQuery => {
+                    let suffix_arrays: Vec<SuffixArray<FmIndexQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    let result = query(suffix_arrays, sequences, locate);
                     // println!("{:?}", result.0);
                     println!("False positives: {:?}", result.1);
                 }
                 QueryType::BloomFilterQuery => {
-                    todo!();
+                    let suffix_arrays: Vec<SuffixArray<BloomFilterQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    let result = query(suffix_arrays, sequences, locate);
+                    // println!("{:?}", result.0);
+                    println!("False positives: {:?}", result.1);
                 }
                 QueryType::PWLLearnedQuery => {
                     let suffix_arrays: Vec<SuffixArray<PWLLearnedQuery>> =
@@ -210,20 +385,156 @@ fn main() -> Result<()> {
                                 format!("Could not decode index with query type {:?}", query_type)
                             })?;
 
-                    let result = query(suffix_arrays, sequences);
+                    let result = query(suffix_arrays, sequences, locate);
                     // println!("{:?}", result.0);
                     println!("False positives: {:?}", result.1);
                 },
             }
         },
+        Args::Count {
+            fasta_file,
+            suffix_array_file,
+            query_type,
+            verified,
+        } => {
+            let sequences = fasta::read_sequences(fasta_file)?;
+            let suffix_array_file = &mut File::open(suffix_array_file)?;
+            let counts = match query_type {
+                QueryType::GroundTruthQuery => {
+                    let suffix_arrays: Vec<SuffixArray<GroundTruthQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    count(&suffix_arrays, &sequences, verified)
+                }
+                QueryType::StandardQuery => {
+                    let suffix_arrays: Vec<SuffixArray<StandardQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    count(&suffix_arrays, &sequences, verified)
+                }
+                QueryType::LcpQuery => {
+                    let suffix_arrays: Vec<SuffixArray<LcpQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    count(&suffix_arrays, &sequences, verified)
+                }
+                QueryType::FmIndexQuery => {
+                    let suffix_arrays: Vec<SuffixArray<FmIndexQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    count(&suffix_arrays, &sequences, verified)
+                }
+                QueryType::BloomFilterQuery => {
+                    let suffix_arrays: Vec<SuffixArray<BloomFilterQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    count(&suffix_arrays, &sequences, verified)
+                }
+                QueryType::PWLLearnedQuery => {
+                    let suffix_arrays: Vec<SuffixArray<PWLLearnedQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    count(&suffix_arrays, &sequences, verified)
+                }
+            };
+
+            for (sequence, count) in sequences.iter().zip(counts) {
+                match count {
+                    Ok(count) => println!("{}\t{}", sequence.description, count),
+                    Err(e) => eprintln!("Query {:?}: {}", sequence.description, e),
+                }
+            }
+        },
+        Args::Stats {
+            suffix_array_file,
+            query_type,
+        } => {
+            let suffix_array_file = &mut File::open(suffix_array_file)?;
+            match query_type {
+                QueryType::GroundTruthQuery => {
+                    let suffix_arrays: Vec<SuffixArray<GroundTruthQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    print_lcp_stats(&suffix_arrays);
+                }
+                QueryType::StandardQuery => {
+                    let suffix_arrays: Vec<SuffixArray<StandardQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    print_lcp_stats(&suffix_arrays);
+                }
+                QueryType::LcpQuery => {
+                    let suffix_arrays: Vec<SuffixArray<LcpQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    print_lcp_stats(&suffix_arrays);
+                }
+                QueryType::FmIndexQuery => {
+                    let suffix_arrays: Vec<SuffixArray<FmIndexQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    print_lcp_stats(&suffix_arrays);
+                }
+                QueryType::BloomFilterQuery => {
+                    let suffix_arrays: Vec<SuffixArray<BloomFilterQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    print_lcp_stats(&suffix_arrays);
+                }
+                QueryType::PWLLearnedQuery => {
+                    let suffix_arrays: Vec<SuffixArray<PWLLearnedQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+
+                    print_lcp_stats(&suffix_arrays);
+                }
+            }
+        },
         Args::Benchmark {
             num_queries,
             match_rate,
             max_query_length,
             suffix_array_file,
             query_type,
+            locate,
         } => {
             let suffix_array_file = &mut File::open(suffix_array_file)?;
+            let index_size = suffix_array_file.metadata()?.len();
 
             let before: Instant;
             let time_elapsed: Duration;
@@ -235,17 +546,10 @@ fn main() -> Result<()> {
                             .with_context(|| {
                                 format!("Could not decode index with query type {:?}", query_type)
                             })?;
-                    let min_len = suffix_arrays[0].w() + suffix_arrays[0].get_underlying_kmers().k() - 1;
-                    if min_len > max_query_length {
-                        return Err(anyhow!("Max query length was not large enough! The minimum query length is {}", min_len));
-                    }
-
-                    let sequences = fasta::generate_sequences(suffix_arrays[0].get_underlying_kmers().get_original_string(), num_queries, match_rate, min_len, max_query_length);
-                    // println!("{:?}", sequences);
-                    println!("Original string length: {:?} bytes", suffix_arrays[0].get_underlying_kmers().get_original_string().len());
+                    let sequences = generate_queries(&suffix_arrays, num_queries, match_rate, max_query_length)?;
 
                     before = Instant::now();
-                    let result = query(suffix_arrays, sequences);
+                    let result = query(suffix_arrays, sequences, locate);
                     time_elapsed = before.elapsed();
 
                     // println!("{:?}", result.0);
@@ -257,24 +561,59 @@ fn main() -> Result<()> {
                             .with_context(|| {
                                 format!("Could not decode index with query type {:?}", query_type)
                             })?;
-                    let min_len = suffix_arrays[0].w() + suffix_arrays[0].get_underlying_kmers().k() - 1;
-                    if min_len > max_query_length {
-                        return Err(anyhow!("Max query length was not large enough! The minimum query length is {}", min_len));
-                    }
+                    let sequences = generate_queries(&suffix_arrays, num_queries, match_rate, max_query_length)?;
+
+                    before = Instant::now();
+                    let result = query(suffix_arrays, sequences, locate);
+                    time_elapsed = before.elapsed();
+
+                    //println!("{:?}", result);
+                    println!("False positives: {:?}", result.1);
+                },
+                QueryType::LcpQuery => {
+                    let suffix_arrays: Vec<SuffixArray<LcpQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+                    let sequences = generate_queries(&suffix_arrays, num_queries, match_rate, max_query_length)?;
 
-                    let sequences = fasta::generate_sequences(suffix_arrays[0].get_underlying_kmers().get_original_string(), num_queries, match_rate, min_len, max_query_length);
-                    //println!("{:?}", sequences);
-                    println!("Original string length: {:?} bytes", suffix_arrays[0].get_underlying_kmers().get_original_string().len());
+                    before = Instant::now();
+                    let result = query(suffix_arrays, sequences, locate);
+                    time_elapsed = before.elapsed();
+
+                    //println!("{:?}", result);
+                    println!("False positives: {:?}", result.1);
+                },
+                QueryType::FmIndexQuery => {
+                    let suffix_arrays: Vec<SuffixArray<FmIndexQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+                    let sequences = generate_queries(&suffix_arrays, num_queries, match_rate, max_query_length)?;
 
                     before = Instant::now();
-                    let result = query(suffix_arrays, sequences);
+                    let result = query(suffix_arrays, sequences, locate);
                     time_elapsed = before.elapsed();
 
                     //println!("{:?}", result);
                     println!("False positives: {:?}", result.1);
                 },
                 QueryType::BloomFilterQuery => {
-                    todo!();
+                    let suffix_arrays: Vec<SuffixArray<BloomFilterQuery>> =
+                        bincode::decode_from_std_read(suffix_array_file, bincode_config)
+                            .with_context(|| {
+                                format!("Could not decode index with query type {:?}", query_type)
+                            })?;
+                    let sequences = generate_queries(&suffix_arrays, num_queries, match_rate, max_query_length)?;
+
+                    before = Instant::now();
+                    let result = query(suffix_arrays, sequences, locate);
+                    time_elapsed = before.elapsed();
+
+                    //println!("{:?}", result.0);
+                    println!("False positives: {:?}", result.1);
                 },
                 QueryType::PWLLearnedQuery => {
                     let suffix_arrays: Vec<SuffixArray<PWLLearnedQuery>> =
@@ -282,17 +621,10 @@ fn main() -> Result<()> {
                             .with_context(|| {
                                 format!("Could not decode index with query type {:?}", query_type)
                             })?;
-                    let min_len = suffix_arrays[0].w() + suffix_arrays[0].get_underlying_kmers().k() - 1;
-                    if min_len > max_query_length {
-                        return Err(anyhow!("Max query length was not large enough! The minimum query length is {}", min_len));
-                    }
-
-                    let sequences = fasta::generate_sequences(suffix_arrays[0].get_underlying_kmers().get_original_string(), num_queries, match_rate, min_len, max_query_length);
-                    //println!("{:?}", sequences);
-                    println!("Original string length: {:?} bytes", suffix_arrays[0].get_underlying_kmers().get_original_string().len());
+                    let sequences = generate_queries(&suffix_arrays, num_queries, match_rate, max_query_length)?;
 
                     before = Instant::now();
-                    let result = query(suffix_arrays, sequences);
+                    let result = query(suffix_arrays, sequences, locate);
                     time_elapsed = before.elapsed();
 
                     //println!("{:?}", result.0);
@@ -301,23 +633,210 @@ fn main() -> Result<()> {
             }
 
             println!("Total time (ms) for performing {:?} queries: {:?}", num_queries, time_elapsed.as_nanos() as f64 / 1000000 as f64);
+            // Report both sides of the space/time tradeoff, e.g. of the suffix array sample rate
+            println!("Index size (bytes): {}", index_size);
+            println!("Time per query (us): {:.3}", time_elapsed.as_nanos() as f64 / 1000.0 / num_queries as f64);
         },
     }
 
     Ok(())
 }
 
-fn query<T: Queryable>(suffix_arrays: Vec<T>, queries: Vec<Sequence>) -> (Vec<(String, usize)>, usize) {
+fn build_suffix_arrays<T: QueryMode>(
+    sequences: &[Sequence],
+    k: usize,
+    sampling: &Sampling,
+    alphabet: &Alphabet,
+    canonical: bool,
+    lcp: bool,
+    init_params: T::InitParams,
+) -> Result<Vec<SuffixArray<T>>>
+where
+    T::InitParams: Clone,
+{
+    let min_len = match sampling {
+        Sampling::Minimizers { w, .. } => w + k - 1,
+        // Syncmer suffix arrays sample single k-mers
+        Sampling::Syncmers(_) => k,
+    };
+    let mut suffix_arrays = Vec::new();
+    for sequence in sequences {
+        if sequence.representation.len() < min_len {
+            eprintln!("Skipping sequence {:?}, which is shorter than {}", sequence.name(), min_len);
+            continue;
+        }
+
+        let kmers = if canonical {
+            KmerSequence::from_bytes_canonical(&sequence.representation, k, alphabet.clone())
+        } else {
+            KmerSequence::from_bytes(&sequence.representation, k, alphabet.clone())
+        }
+        .context("Sequence contains characters missing from the alphabet")?;
+
+        let masked = kmers.masked_intervals();
+        if !masked.is_empty() {
+            println!(
+                "Masked {} bases in {} intervals of sequence {:?}",
+                masked.iter().map(|(start, end)| end - start).sum::<usize>(),
+                masked.len(),
+                sequence.name()
+            );
+        }
+        let suffix_array = match sampling {
+            &Sampling::Minimizers { w, order, hash_seed } => {
+                SuffixArray::<T>::from_kmers_seeded(kmers, w, order, hash_seed, init_params.clone())
+            }
+            Sampling::Syncmers(params) => {
+                SuffixArray::<T>::from_kmers_syncmers(kmers, params.clone(), init_params.clone())
+            }
+        }
+        .with_name(sequence.name());
+
+        suffix_arrays.push(if lcp { suffix_array.with_lcp() } else { suffix_array });
+    }
+
+    if suffix_arrays.is_empty() {
+        return Err(anyhow!("Expected at least one sequence of length {} in FASTA file", min_len));
+    }
+
+    Ok(suffix_arrays)
+}
+
+/// Reports how many super-k-mers each record was sampled into, and how many there would be
+/// if windows with equal minimizers at different positions weren't merged.
+fn print_sampling_stats<T: QueryMode>(suffix_arrays: &[SuffixArray<T>]) {
+    for suffix_array in suffix_arrays {
+        print!("Super-k-mers of sequence {:?}: {}", suffix_array.name(), suffix_array.num_super_kmers());
+        if let Some(num_positions) = suffix_array.num_minimizer_positions() {
+            print!(" ({} if merged by minimizer position)", num_positions);
+        }
+        println!();
+    }
+}
+
+/// Summarizes the LCP array of each record: its mean and maximum, and a histogram with
+/// power-of-two buckets. The LCP array is computed if the index doesn't store it.
+fn print_lcp_stats<T: QueryMode>(suffix_arrays: &[SuffixArray<T>]) {
+    for suffix_array in suffix_arrays {
+        println!("Sequence {:?}: {} super-k-mers", suffix_array.name(), suffix_array.num_super_kmers());
+        let computed;
+        let lcp = match suffix_array.lcp() {
+            Some(lcp) => lcp,
+            None => match suffix_array.compute_lcp() {
+                Some(lcp) => {
+                    computed = lcp;
+                    &computed
+                }
+                None => {
+                    println!("LCP array not available: the query type doesn't store the suffix array");
+                    continue;
+                }
+            },
+        };
+
+        // lcp[0] doesn't compare two suffixes
+        let lcp = &lcp[1..];
+        if lcp.is_empty() {
+            continue;
+        }
+        let max = *lcp.iter().max().unwrap();
+        println!("LCP mean: {:.3}, max: {}", lcp.iter().sum::<usize>() as f64 / lcp.len() as f64, max);
+
+        // Bucket 0 holds LCP 0, and bucket b > 0 holds LCPs in [2^(b-1), 2^b)
+        let bucket = |x: usize| (usize::BITS - x.leading_zeros()) as usize;
+        let mut histogram = vec![0; bucket(max) + 1];
+        for &x in lcp {
+            histogram[bucket(x)] += 1;
+        }
+        for (b, &count) in histogram.iter().enumerate() {
+            let range = match b {
+                0 => "0".to_owned(),
+                1 => "1".to_owned(),
+                _ => format!("{}-{}", 1 << (b - 1), (1 << b) - 1),
+            };
+            println!("  LCP {}:\t{}", range, count);
+        }
+    }
+}
+
+fn generate_queries<T: QueryMode>(suffix_arrays: &[SuffixArray<T>], num_queries: usize, match_rate: f64, max_query_length: usize) -> Result<Vec<Sequence>> {
+    let min_len = suffix_arrays[0].w() + suffix_arrays[0].get_underlying_kmers().k() - 1;
+    if min_len > max_query_length {
+        return Err(anyhow!("Max query length was not large enough! The minimum query length is {}", min_len));
+    }
+
+    let references: Vec<Vec<u8>> = suffix_arrays
+        .iter()
+        .map(|suffix_array| {
+            let reference = suffix_array.get_underlying_kmers().get_reference();
+            reference.decode(0..reference.len())
+        })
+        .collect();
+    let references: Vec<&[u8]> = references.iter().map(|reference| reference.as_slice()).collect();
+
+    let sequences = fasta::generate_sequences(&references, num_queries, match_rate, min_len, max_query_length);
+    //println!("{:?}", sequences);
+    println!("Original string length: {:?} bytes", references.iter().map(|r| r.len()).sum::<usize>());
+
+    Ok(sequences)
+}
+
+/// Counts the occurrences of each query, summed over every record.
+fn count<T: QueryMode>(suffix_arrays: &[SuffixArray<T>], queries: &[Sequence], verified: bool) -> Vec<Result<usize>>
+where
+    SuffixArray<T>: Queryable,
+{
+    queries
+        .iter()
+        .map(|query| {
+            // Every record shares the same alphabet, k and w
+            suffix_arrays[0].validate_query(&query.representation)?;
+
+            Ok(suffix_arrays
+                .iter()
+                .map(|suffix_array| {
+                    let mut count = suffix_array.count(&query.representation, verified);
+                    if suffix_array.get_underlying_kmers().is_canonical() {
+                        count += suffix_array.count(&reverse_complement(&query.representation), verified);
+                    }
+                    count
+                })
+                .sum())
+        })
+        .collect()
+}
+
+/// Queries every record, reporting hits as (query description, record name, offset in record,
+/// strand). Indices built in DNA mode are searched on both strands.
+fn query<T: QueryMode>(suffix_arrays: Vec<SuffixArray<T>>, queries: Vec<Sequence>, locate: LocateMode) -> (Vec<(String, String, usize, Strand)>, usize)
+where
+    SuffixArray<T>: Queryable,
+{
+    // Every record shares the same alphabet, k and w
+    let queries: Vec<_> = queries
+        .into_iter()
+        .filter(|query| match suffix_arrays[0].validate_query(&query.representation) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Query {:?}: {}", query.description, e);
+                false
+            }
+        })
+        .collect();
+
     let mut result = Vec::new();
     let mut false_positives = 0;
-    //for (i, suffix_array) in suffix_arrays.into_iter().enumerate() {
     for suffix_array in suffix_arrays.into_iter() {
         for query in &queries {
-            let query_results = suffix_array.query(&query.representation);
+            let query_results = if suffix_array.get_underlying_kmers().is_canonical() {
+                suffix_array.locate_stranded(&query.representation, locate)
+            } else {
+                let (hits, false_positives) = suffix_array.locate(&query.representation, locate);
+                (hits.into_iter().map(|i| (i, Strand::Forward)).collect(), false_positives)
+            };
             false_positives += query_results.1;
-            for query_result in query_results.0 {
-                //result.push((i, query_result.0));
-                result.push((query.description.clone(), query_result));
+            for (query_result, strand) in query_results.0 {
+                result.push((query.description.clone(), suffix_array.name().to_owned(), query_result, strand));
             }
         }
     }
@@ -334,7 +853,7 @@ mod tests {
         let k = 3;
         let w = 3;
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         println!("kmers: {:#?}", kmers);
 
         println!("----------");
@@ -343,4 +862,33 @@ mod tests {
         let query_result = suffix_array.query("CTGAC".as_bytes());
         println!("{:#?}", query_result);
     }
+
+    #[test]
+    fn multiple_records() {
+        let sequences = vec![
+            Sequence { description: "chr1 first".to_string(), representation: b"ACTGACCCGTAGCGCTA".to_vec() },
+            Sequence { description: "plasmid".to_string(), representation: b"GGCATTACAGT".to_vec() },
+            Sequence { description: "tiny".to_string(), representation: b"AC".to_vec() },
+        ];
+        let alphabet = Alphabet::from_sequences(sequences.iter().map(|s| s.representation.as_slice()));
+        let sampling = Sampling::Minimizers { w: 3, order: MinimizerOrder::Lexicographic, hash_seed: 0 };
+        let suffix_arrays =
+            build_suffix_arrays::<StandardQuery>(&sequences, 3, &sampling, &alphabet, false, false, ()).unwrap();
+        assert_eq!(suffix_arrays.len(), 2);
+
+        let queries = vec![
+            Sequence { description: "q1".to_string(), representation: b"CATTAC".to_vec() },
+            Sequence { description: "q2".to_string(), representation: b"CCGTAG".to_vec() },
+            // Spans the boundary between the first two records
+            Sequence { description: "q3".to_string(), representation: b"GCTAGGC".to_vec() },
+        ];
+        let (result, _) = query(suffix_arrays, queries, LocateMode::All);
+        assert_eq!(
+            result,
+            vec![
+                ("q2".to_string(), "chr1".to_string(), 6, Strand::Forward),
+                ("q1".to_string(), "plasmid".to_string(), 2, Strand::Forward),
+            ]
+        );
+    }
 }
@@ -0,0 +1,417 @@
+// Compact columnar storage for the super-k-mers of a suffix array (Elias-Fano coded start
+// positions, fixed-width lengths, and minimizers stored as ranks into an Elias-Fano coded table
+// of the distinct minimizers), and for the reference string itself.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use bincode::{Decode, Encode};
+
+use crate::transform::{Alphabet, Kmer, KmerSequence, SuperKmer};
+use crate::wavelet::RankBitVec;
+
+/// The number of bits needed to store every integer up to `max`.
+fn bits_needed(max: usize) -> usize {
+    (usize::BITS - max.leading_zeros()) as usize
+}
+
+/// Unsigned integers of a fixed number of bits, packed into 64-bit words.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PackedInts {
+    words: Vec<u64>,
+    width: usize,
+    len: usize,
+}
+
+impl PackedInts {
+    /// Packs `values` with the fewest bits that fit the largest one.
+    pub fn from_values(values: impl ExactSizeIterator<Item = usize> + Clone) -> Self {
+        let width = bits_needed(values.clone().max().unwrap_or(0));
+        Self::with_width(width, values)
+    }
+
+    pub fn with_width(width: usize, values: impl ExactSizeIterator<Item = usize>) -> Self {
+        assert!(width <= 64);
+        let len = values.len();
+        let mut words = vec![0u64; (len * width).div_ceil(64)];
+        for (i, value) in values.enumerate() {
+            let value = value as u64;
+            assert!(width == 64 || value >> width == 0, "{} doesn't fit in {} bits", value, width);
+            if width == 0 {
+                continue;
+            }
+            let (word, offset) = (i * width / 64, i * width % 64);
+            words[word] |= value << offset;
+            if offset + width > 64 {
+                words[word + 1] |= value >> (64 - offset);
+            }
+        }
+        Self { words, width, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, i: usize) -> usize {
+        assert!(i < self.len);
+        if self.width == 0 {
+            return 0;
+        }
+        let (word, offset) = (i * self.width / 64, i * self.width % 64);
+        let mut value = self.words[word] >> offset;
+        if offset + self.width > 64 {
+            value |= self.words[word + 1] << (64 - offset);
+        }
+        let mask = if self.width == 64 { u64::MAX } else { (1 << self.width) - 1 };
+        (value & mask) as usize
+    }
+}
+
+/// A non-decreasing sequence of integers in Elias-Fano coding, taking about
+/// 2 + log(max / len) bits per element. The low bits of each element are stored verbatim,
+/// and the high bits in unary as the gaps between set bits of a bit vector.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+pub struct EliasFano {
+    low: PackedInts,
+    /// Element i sets bit `(value >> low.width) + i`
+    high: RankBitVec,
+}
+
+impl EliasFano {
+    pub fn from_values(values: impl ExactSizeIterator<Item = usize> + Clone) -> Self {
+        let len = values.len();
+        let max = values.clone().last().unwrap_or(0);
+        let low_width = if max > len { bits_needed(max / len.max(1)) - 1 } else { 0 };
+
+        let low = PackedInts::with_width(low_width, values.clone().map(|value| value & ((1 << low_width) - 1)));
+        let mut high_bits = vec![false; len + (max >> low_width) + 1];
+        let mut previous = 0;
+        for (i, value) in values.enumerate() {
+            assert!(value >= previous, "Elias-Fano values must be non-decreasing");
+            previous = value;
+            high_bits[(value >> low_width) + i] = true;
+        }
+
+        Self { low, high: RankBitVec::with_select(high_bits) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.low.len()
+    }
+
+    pub fn get(&self, i: usize) -> usize {
+        let high = self.high.select1(i) - i;
+        high << self.low.width | self.low.get(i)
+    }
+}
+
+/// The distinct minimizers of a suffix array, sorted by `compare_kmers`, excluding the
+/// sentinel. Packed k-mers are integers in the same order, so if they fit in a word they're
+/// Elias-Fano coded, taking about 2 + log(alphabet size ^ k / len) bits each.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+pub enum MinimizerTable {
+    Packed(EliasFano),
+    /// K-mers too long to fit in a word
+    Kmers(Vec<Kmer>),
+}
+
+impl MinimizerTable {
+    pub fn new(minimizers: Vec<Kmer>) -> Self {
+        let fits_in_word = |minimizer: &Kmer| matches!(minimizer, &Kmer::Data(x) if x <= usize::MAX as u128);
+        if minimizers.iter().all(fits_in_word) {
+            let values = minimizers.iter().map(|minimizer| match minimizer {
+                &Kmer::Data(x) => x as usize,
+                _ => unreachable!(),
+            });
+            Self::Packed(EliasFano::from_values(values))
+        } else {
+            Self::Kmers(minimizers)
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Packed(values) => values.len(),
+            Self::Kmers(minimizers) => minimizers.len(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, i: usize) -> Kmer {
+        match self {
+            Self::Packed(values) => Kmer::Data(values.get(i) as u128),
+            Self::Kmers(minimizers) => minimizers[i].clone(),
+        }
+    }
+
+    /// Finds `minimizer` like `slice::binary_search`, returning its rank if present and
+    /// otherwise the rank it would have.
+    pub fn search(&self, kmers: &KmerSequence, minimizer: &Kmer) -> Result<usize, usize> {
+        let values = match self {
+            Self::Packed(values) => values,
+            Self::Kmers(minimizers) => return minimizers.binary_search_by(|x| kmers.compare_kmers(x, minimizer)),
+        };
+        // K-mers that don't fit in a word, and the sentinel, are greater than every stored one
+        let x = match minimizer {
+            &Kmer::Data(x) if x <= usize::MAX as u128 => x as usize,
+            Kmer::Long(_) => panic!("compared k-mers of different lengths"),
+            _ => return Err(values.len()),
+        };
+
+        let (mut left, mut right) = (0, values.len());
+        while left < right {
+            let mid = (left + right) / 2;
+            match values.get(mid).cmp(&x) {
+                Ordering::Less => left = mid + 1,
+                Ordering::Equal => return Ok(mid),
+                Ordering::Greater => right = mid,
+            }
+        }
+        Err(left)
+    }
+}
+
+/// The super-k-mers of a suffix array, behind accessors for each field.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PackedSuperKmers {
+    start_pos: EliasFano,
+    lengths: PackedInts,
+    /// The rank of each super-k-mer's minimizer in `minimizers`, with the sentinel ranked
+    /// last, or empty if the ranks aren't stored
+    minimizer_ranks: PackedInts,
+    minimizers: MinimizerTable,
+}
+
+impl PackedSuperKmers {
+    /// Packs `super_kmers`, whose minimizers have the given 0-based ranks among the distinct
+    /// minimizers in `minimizers`. Without ranks, the super-k-mers' minimizers can't be
+    /// recovered, though the table of distinct minimizers is still kept.
+    pub fn new(super_kmers: &[SuperKmer], minimizer_ranks: Option<&[usize]>, minimizers: MinimizerTable) -> Self {
+        // Super-k-mers are ordered by their first window, so start positions never decrease
+        let start_pos = EliasFano::from_values(super_kmers.iter().map(|super_kmer| super_kmer.start_pos));
+        let lengths = PackedInts::from_values(super_kmers.iter().map(|super_kmer| super_kmer.length));
+        let minimizer_ranks = match minimizer_ranks {
+            Some(ranks) => {
+                assert_eq!(ranks.len(), super_kmers.len());
+                PackedInts::from_values(ranks.iter().copied())
+            }
+            None => PackedInts::from_values([].into_iter()),
+        };
+        Self { start_pos, lengths, minimizer_ranks, minimizers }
+    }
+
+    /// The number of super-k-mers, including the sentinel.
+    pub fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub fn start_pos(&self, i: usize) -> usize {
+        self.start_pos.get(i)
+    }
+
+    pub fn length(&self, i: usize) -> usize {
+        self.lengths.get(i)
+    }
+
+    /// The rank of the minimizer of super-k-mer `i` among the distinct minimizers. Super-k-mers
+    /// compare like their ranks.
+    pub fn minimizer_rank(&self, i: usize) -> usize {
+        self.minimizer_ranks.get(i)
+    }
+
+    #[cfg(test)]
+    pub fn minimizer(&self, i: usize) -> Kmer {
+        match self.minimizer_rank(i) {
+            rank if rank == self.minimizers.len() => Kmer::Sentinel,
+            rank => self.minimizers.get(rank),
+        }
+    }
+
+    /// The distinct minimizers of the super-k-mers.
+    pub fn minimizers(&self) -> &MinimizerTable {
+        &self.minimizers
+    }
+
+    /// Maps the minimizers of a query to keys that compare with `stored_key` like the
+    /// minimizers compare with each other. Minimizers absent from the reference get a key
+    /// between those of their neighbours, so that they never match.
+    pub fn query_keys(&self, kmers: &KmerSequence, query_super_kmers: &[SuperKmer]) -> Vec<usize> {
+        query_super_kmers
+            .iter()
+            .map(|super_kmer| {
+                match self.minimizers.search(kmers, &super_kmer.minimizer) {
+                    Ok(rank) => 2 * rank + 1,
+                    Err(rank) => 2 * rank,
+                }
+            })
+            .collect()
+    }
+
+    /// The key of super-k-mer `i` (see `query_keys`).
+    pub fn stored_key(&self, i: usize) -> usize {
+        2 * self.minimizer_rank(i) + 1
+    }
+
+    /// Compares the first `query_keys.len()` super-k-mers of the suffix starting at `suffix`
+    /// with the query, starting after the first `start`, which are known to match. Returns the
+    /// number of matching super-k-mers along with the ordering.
+    pub fn compare_suffix(&self, suffix: usize, query_keys: &[usize], start: usize) -> (usize, Ordering) {
+        // The sentinel is greater than every query key, so the suffix can't run out first
+        for (h, &key) in query_keys.iter().enumerate().skip(start) {
+            match self.stored_key(suffix + h).cmp(&key) {
+                Ordering::Equal => {}
+                ordering => return (h, ordering),
+            }
+        }
+        (query_keys.len(), Ordering::Equal)
+    }
+}
+
+/// A string stored with as few bits per character as its alphabet's residues (see
+/// `Alphabet::residues`) need. The bytes of masked intervals, which are outside the alphabet,
+/// are kept verbatim. Case is folded if the alphabet ignores it.
+#[derive(Debug, Encode, Decode)]
+pub struct PackedSequence {
+    codes: PackedInts,
+    /// The residue of each code
+    residues: Vec<u8>,
+    /// Sorted, disjoint [start, end) intervals holding bytes outside the alphabet
+    masked: Vec<(usize, usize)>,
+    /// The bytes of each masked interval
+    masked_bytes: Vec<Vec<u8>>,
+}
+
+impl PackedSequence {
+    /// Packs `sequence`, whose bytes outside `alphabet` are exactly those in `masked`.
+    pub fn new(sequence: &[u8], alphabet: &Alphabet, masked: Vec<(usize, usize)>) -> Self {
+        let residues = alphabet.residues();
+        let width = bits_needed(residues.len().saturating_sub(1)).max(1);
+        // Masked bytes are given an arbitrary code
+        let codes = PackedInts::with_width(
+            width,
+            sequence.iter().map(|&b| {
+                alphabet
+                    .residue(b)
+                    .map_or(0, |residue| residues.binary_search(&residue).unwrap())
+            }),
+        );
+        let masked_bytes = masked.iter().map(|&(start, end)| sequence[start..end].to_vec()).collect();
+        Self { codes, residues, masked, masked_bytes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn masked_intervals(&self) -> &[(usize, usize)] {
+        &self.masked
+    }
+
+    /// Replaces the contents of `buffer` with the characters in `range`.
+    pub fn decode_into(&self, range: Range<usize>, buffer: &mut Vec<u8>) {
+        assert!(range.start <= range.end && range.end <= self.len());
+        buffer.clear();
+        buffer.extend(range.clone().map(|i| self.residues[self.codes.get(i)]));
+
+        let first = self.masked.partition_point(|&(_, end)| end <= range.start);
+        for (&(start, end), bytes) in self.masked[first..].iter().zip(&self.masked_bytes[first..]) {
+            if start >= range.end {
+                break;
+            }
+            let (from, to) = (start.max(range.start), end.min(range.end));
+            buffer[from - range.start..to - range.start].copy_from_slice(&bytes[from - start..to - start]);
+        }
+    }
+
+    /// The characters in `range`.
+    pub fn decode(&self, range: Range<usize>) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(range.len());
+        self.decode_into(range, &mut buffer);
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::transform::{AlphabetReduction, MinimizerOrder};
+
+    #[test]
+    fn packed_ints_roundtrip() {
+        let mut rng = rand::rng();
+        for width in [0, 1, 3, 7, 13, 32, 63, 64] {
+            let values: Vec<usize> = (0..500)
+                .map(|_| if width == 0 { 0 } else { rng.random::<u64>() as usize >> (64 - width) })
+                .collect();
+            let packed = PackedInts::with_width(width, values.iter().copied());
+            assert_eq!((0..packed.len()).map(|i| packed.get(i)).collect::<Vec<_>>(), values);
+        }
+    }
+
+    #[test]
+    fn elias_fano_roundtrip() {
+        let mut rng = rand::rng();
+        for (len, max_gap) in [(0, 1), (1, 1), (100, 1), (100, 5), (1000, 1000), (50, 1 << 40)] {
+            let mut value = 0;
+            let values: Vec<usize> = (0..len)
+                .map(|_| {
+                    value += rng.random_range(0..max_gap);
+                    value
+                })
+                .collect();
+            let elias_fano = EliasFano::from_values(values.iter().copied());
+            assert_eq!((0..len).map(|i| elias_fano.get(i)).collect::<Vec<_>>(), values);
+        }
+    }
+
+    #[test]
+    fn minimizer_table_matches_sorted_kmers() {
+        let mut rng = rand::rng();
+        let mut random_sequence = |len| -> Vec<u8> { (0..len).map(|_| b"ACGT"[rng.random_range(0..4)]).collect() };
+        let (reference, query) = (random_sequence(2000), random_sequence(2000));
+
+        // 4-mers and 16-mers fit in a word, but 40-mers don't
+        for (k, packed) in [(4, true), (16, true), (40, false)] {
+            let all_kmers = |sequence| {
+                let kmers = KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                let super_kmers = kmers.compute_super_kmers(1, MinimizerOrder::Lexicographic, 0, None).unwrap();
+                (kmers, super_kmers.into_iter().map(|super_kmer| super_kmer.minimizer).collect::<Vec<_>>())
+            };
+            let (kmers, mut minimizers) = all_kmers(&reference);
+            minimizers.sort_by(|x, y| kmers.compare_kmers(x, y));
+            minimizers.dedup();
+
+            let table = MinimizerTable::new(minimizers.clone());
+            assert_eq!(matches!(table, MinimizerTable::Packed(_)), packed);
+            assert_eq!(table.len(), minimizers.len());
+            assert!((0..table.len()).all(|i| table.get(i) == minimizers[i]));
+            for minimizer in all_kmers(&query).1.iter().chain([&Kmer::Sentinel]) {
+                let expected = minimizers.binary_search_by(|x| kmers.compare_kmers(x, minimizer));
+                assert_eq!(table.search(&kmers, minimizer), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_sequence_roundtrip() {
+        let sequence = b"NNACGTacgtNNNNAGCTnRYACGTN";
+        let masked = vec![(0, 2), (10, 14), (18, 21), (25, 26)];
+        let packed = PackedSequence::new(sequence, &Alphabet::dna(), masked);
+        let expected = b"NNACGTACGTNNNNAGCTnRYACGTN";
+        for start in 0..=sequence.len() {
+            for end in start..=sequence.len() {
+                assert_eq!(packed.decode(start..end), &expected[start..end]);
+            }
+        }
+
+        let sequence = b"MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEK";
+        for alphabet in [Alphabet::from_bytes(sequence), Alphabet::protein(Some(AlphabetReduction::Dayhoff6))] {
+            let packed = PackedSequence::new(sequence, &alphabet, Vec::new());
+            assert_eq!(packed.decode(0..sequence.len()), sequence);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Linear-time suffix array construction by induced sorting (SA-IS), following
+// Nong, Zhang and Chan, "Two Efficient Algorithms for Linear Time Suffix Array Construction".
+
+const EMPTY: usize = usize::MAX;
+
+/// Builds the suffix array of `text`, whose characters must all be less than `alphabet_size`.
+/// The last character must be a unique 0, which sorts before every other suffix.
+pub fn suffix_array(text: &[usize], alphabet_size: usize) -> Vec<usize> {
+    assert_eq!(text.last(), Some(&0), "text must end with a 0 terminator");
+    assert!(text[..text.len() - 1].iter().all(|&c| 0 < c && c < alphabet_size));
+    sais(text, alphabet_size)
+}
+
+fn sais(text: &[usize], alphabet_size: usize) -> Vec<usize> {
+    let n = text.len();
+    if n == 1 {
+        return vec![0];
+    }
+
+    // S-type suffixes are smaller than the following suffix, L-type ones larger
+    let mut is_s = vec![false; n];
+    is_s[n - 1] = true;
+    for i in (0..n - 1).rev() {
+        is_s[i] = text[i] < text[i + 1] || (text[i] == text[i + 1] && is_s[i + 1]);
+    }
+    // Leftmost S-type positions, i.e. S-type positions preceded by an L-type one
+    let is_lms = |i: usize| i > 0 && is_s[i] && !is_s[i - 1];
+
+    let mut bucket_sizes = vec![0; alphabet_size];
+    for &c in text {
+        bucket_sizes[c] += 1;
+    }
+
+    // Sort the LMS substrings by placing the LMS positions at the ends of their buckets
+    let lms_positions: Vec<usize> = (1..n).filter(|&i| is_lms(i)).collect();
+    let mut suffix_array = induce(text, &is_s, &bucket_sizes, &lms_positions);
+
+    // Name each LMS substring by its rank among the distinct LMS substrings
+    let sorted_lms: Vec<usize> = suffix_array.iter().copied().filter(|&i| is_lms(i)).collect();
+    let lms_substrings_equal = |a: usize, b: usize| {
+        if a == n - 1 || b == n - 1 {
+            return a == b;
+        }
+        for i in 0.. {
+            if text[a + i] != text[b + i] || is_s[a + i] != is_s[b + i] {
+                return false;
+            }
+            if i > 0 && (is_lms(a + i) || is_lms(b + i)) {
+                return is_lms(a + i) && is_lms(b + i);
+            }
+        }
+        unreachable!()
+    };
+    suffix_array.fill(EMPTY);
+    let mut name = 0;
+    for (i, &position) in sorted_lms.iter().enumerate() {
+        if i > 0 && !lms_substrings_equal(sorted_lms[i - 1], position) {
+            name += 1;
+        }
+        // LMS positions are at least two apart, so position / 2 is unique
+        suffix_array[position / 2] = name;
+    }
+    let num_names = name + 1;
+    let reduced_text: Vec<usize> = suffix_array.iter().copied().filter(|&name| name != EMPTY).collect();
+
+    // Sort the LMS suffixes, recursing if two LMS substrings are equal
+    let sorted_lms: Vec<usize> = if num_names < reduced_text.len() {
+        sais(&reduced_text, num_names)
+            .into_iter()
+            .map(|i| lms_positions[i])
+            .collect()
+    } else {
+        sorted_lms
+    };
+
+    induce(text, &is_s, &bucket_sizes, &sorted_lms)
+}
+
+/// Places `lms_positions` at the ends of their buckets in reverse order, then induces the
+/// order of the L-type suffixes from left to right and of the S-type suffixes from right to left.
+fn induce(text: &[usize], is_s: &[bool], bucket_sizes: &[usize], lms_positions: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    let mut suffix_array = vec![EMPTY; n];
+
+    let bucket_heads = |heads: &mut Vec<usize>| {
+        let mut sum = 0;
+        for (head, &size) in heads.iter_mut().zip(bucket_sizes) {
+            *head = sum;
+            sum += size;
+        }
+    };
+    let bucket_tails = |tails: &mut Vec<usize>| {
+        let mut sum = 0;
+        for (tail, &size) in tails.iter_mut().zip(bucket_sizes) {
+            sum += size;
+            *tail = sum;
+        }
+    };
+    let mut bucket = vec![0; bucket_sizes.len()];
+
+    bucket_tails(&mut bucket);
+    for &i in lms_positions.iter().rev() {
+        bucket[text[i]] -= 1;
+        suffix_array[bucket[text[i]]] = i;
+    }
+
+    bucket_heads(&mut bucket);
+    for k in 0..n {
+        let i = suffix_array[k];
+        if i != EMPTY && i > 0 && !is_s[i - 1] {
+            suffix_array[bucket[text[i - 1]]] = i - 1;
+            bucket[text[i - 1]] += 1;
+        }
+    }
+
+    bucket_tails(&mut bucket);
+    for k in (0..n).rev() {
+        let i = suffix_array[k];
+        if i != EMPTY && i > 0 && is_s[i - 1] {
+            bucket[text[i - 1]] -= 1;
+            suffix_array[bucket[text[i - 1]]] = i - 1;
+        }
+    }
+
+    suffix_array
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn matches_naive_sort() {
+        let mut rng = rand::rng();
+
+        for _ in 0..500 {
+            let len = rng.random_range(0..100);
+            let alphabet_size = rng.random_range(2..8);
+            let mut text: Vec<usize> = (0..len).map(|_| rng.random_range(1..alphabet_size)).collect();
+            text.push(0);
+
+            let mut expected: Vec<usize> = (0..text.len()).collect();
+            expected.sort_by(|&i, &j| text[i..].cmp(&text[j..]));
+            assert_eq!(suffix_array(&text, alphabet_size), expected, "{:?}", text);
+        }
+
+        // Highly repetitive texts recurse several times
+        let mut text = vec![1; 1000];
+        text.push(0);
+        assert_eq!(suffix_array(&text, 2), (0..text.len()).rev().collect::<Vec<_>>());
+    }
+}
@@ -1,11 +1,17 @@
 use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
+use anyhow::{bail, Result};
 use bincode::{Decode, Encode};
 use fastbloom::BloomFilter;
+use itertools::Itertools;
 use plr::regression::GreedyPLR;
+use rayon::prelude::*;
 
-use crate::iter_order_by::MyIterOrderBy;
-use crate::transform::{Kmer, KmerSequence, SuperKmer, MinimizerOrder};
+use crate::packed::{EliasFano, MinimizerTable, PackedInts, PackedSuperKmers};
+use crate::sais;
+use crate::transform::{reverse_complement, Kmer, KmerSequence, SuperKmer, MinimizerOrder, SyncmerParams};
+use crate::wavelet::{RankBitVec, WaveletMatrix};
 
 /// A suffix array, constructed over a sequence of kmers.
 ///
@@ -24,13 +30,29 @@ use crate::transform::{Kmer, KmerSequence, SuperKmer, MinimizerOrder};
 /// ```
 #[derive(Debug, Encode, Decode)]
 pub struct SuffixArray<T> {
+    /// The name of the FASTA record this suffix array was built over
+    name: String,
+
     underlying_kmers: KmerSequence,
     w: usize,
     minimizer_order: MinimizerOrder,
+    /// The seed of the hash-based minimizer orders, kept so that queries choose the same minimizers
+    hash_seed: u64,
+    /// Set if super-k-mers are single syncmers rather than runs of windows sharing a
+    /// minimizer, in which case `w` is 1 and `minimizer_order` is unused
+    syncmers: Option<SyncmerParams>,
+    /// The number of super-k-mers if windows were merged by minimizer position rather than
+    /// value (see `KmerSequence::compute_super_kmers_counting_positions`), or None for syncmers
+    num_minimizer_positions: Option<usize>,
 
     // NOTE: we avoid storing a Vec<&[SuperKmer]> to make serialization easier
-    super_kmers: Vec<SuperKmer>,
+    /// The minimizer ranks are only stored if `T::STORES_SUFFIX_ARRAY`
+    super_kmers: PackedSuperKmers,
+    /// Empty unless `T::STORES_SUFFIX_ARRAY`
     suffix_array: Vec<usize>,
+    /// If computed, `lcp[i]` is the number of leading super-k-mers with equal minimizers
+    /// shared by the suffixes at `suffix_array[i - 1]` and `suffix_array[i]`, and `lcp[0]` is 0
+    lcp: Option<Vec<usize>>,
 
     /// Stores any auxillary data structures required by non-standard query modes
     query_mode_aux_data: T,
@@ -39,6 +61,11 @@ pub struct SuffixArray<T> {
 pub trait QueryMode {
     type InitParams;
 
+    /// Whether the suffix array and the minimizer ranks of the super-k-mers are kept after
+    /// `initialize_aux_data`. Query modes that search their own structures instead only need
+    /// the positions of the super-k-mers, to verify candidates, and the distinct minimizers.
+    const STORES_SUFFIX_ARRAY: bool = true;
+
     fn initialize_aux_data(
         kmers: &KmerSequence,
         w: usize,
@@ -47,8 +74,60 @@ pub trait QueryMode {
     ) -> Self;
 }
 
+/// How many occurrences of a query to report.
+#[derive(Debug, PartialEq, Copy, Clone, clap::ValueEnum)]
+pub enum LocateMode {
+    /// Every occurrence, sorted and de-duplicated
+    All,
+    /// Only the first verified occurrence, if any
+    First,
+}
+
+/// The strand of the reference a hit was found on.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Strand {
+    Forward,
+    /// The reverse complement of the query occurs at the reported position
+    Reverse,
+}
+
 pub trait Queryable {
-    fn query(&self, query: &[u8]) -> (Vec<usize>, usize);
+    /// Returns the occurrences of `query` found under `mode`, along with the number of
+    /// candidates that failed verification (false positives).
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize);
+
+    /// Returns every occurrence of `query`.
+    #[cfg(test)]
+    fn query(&self, query: &[u8]) -> (Vec<usize>, usize) {
+        self.locate(query, LocateMode::All)
+    }
+
+    /// Like `locate`, but also searches for the reverse complement of `query`, tagging each
+    /// hit with its strand. A reverse-complement palindrome is reported on both strands.
+    fn locate_stranded(&self, query: &[u8], mode: LocateMode) -> (Vec<(usize, Strand)>, usize) {
+        let (forward, forward_false_positives) = self.locate(query, mode);
+        let (reverse, reverse_false_positives) = self.locate(&reverse_complement(query), mode);
+
+        let result = forward
+            .into_iter()
+            .map(|i| (i, Strand::Forward))
+            .chain(reverse.into_iter().map(|i| (i, Strand::Reverse)))
+            .collect();
+        (result, forward_false_positives + reverse_false_positives)
+    }
+
+    /// Counts the occurrences of `query` without reporting their positions. If `verified`
+    /// is false, this is the number of suffixes matching the query in minimizer space,
+    /// which skips the string comparisons but may differ from the true count.
+    fn count(&self, query: &[u8], verified: bool) -> usize;
+}
+
+/// The interval of a suffix array whose suffixes start with a query's minimizers.
+struct Candidates<'a> {
+    /// The (possibly narrowed) part of the suffix array that was searched
+    suffix_array: &'a [usize],
+    range: (usize, usize),
+    query_super_kmers: Vec<SuperKmer>,
 }
 
 #[derive(Encode, Decode)]
@@ -83,11 +162,26 @@ impl QueryMode for StandardQuery {
     }
 }
 
-// NOTE: this is a placeholder
+/// The longest minimizer-space substring (in super-k-mers) stored in the bloom filters
+const BLOOM_FILTER_MAX_NGRAM: usize = 3;
+
 #[derive(Encode, Decode)]
 pub struct BloomFilterQuery {
+    /// `filters[i]` stores every run of `i + 1` consecutive minimizers in the reference
     #[bincode(with_serde)]
-    example: BloomFilter,
+    filters: Vec<BloomFilter>,
+}
+
+/// Hashes only the minimizers of a run of super-k-mers, so that the same minimizer-space
+/// substring hashes identically in the reference and in a query.
+struct MinimizerNgram<'a>(&'a [SuperKmer]);
+
+impl Hash for MinimizerNgram<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for super_kmer in self.0 {
+            super_kmer.minimizer.hash(state);
+        }
+    }
 }
 
 impl QueryMode for BloomFilterQuery {
@@ -97,24 +191,117 @@ impl QueryMode for BloomFilterQuery {
     fn initialize_aux_data(
         _kmers: &KmerSequence,
         _w: usize,
-        _suffix_array: &[&[SuperKmer]],
-        _init_params: Self::InitParams,
+        suffix_array: &[&[SuperKmer]],
+        init_params: Self::InitParams,
     ) -> Self {
-        todo!()
+        let fpr: f64 = init_params.into();
+
+        // Every n-gram in the minimizer sequence is the prefix of exactly one suffix
+        let mut filters: Vec<_> = (0..BLOOM_FILTER_MAX_NGRAM)
+            .map(|_| {
+                BloomFilter::with_false_pos(fpr)
+                    .seed(&0)
+                    .expected_items(suffix_array.len())
+            })
+            .collect();
+        for &suffix in suffix_array {
+            for (i, filter) in filters.iter_mut().enumerate() {
+                let Some(ngram) = suffix.get(0..i + 1) else { break };
+                if ngram.iter().any(|s| s.minimizer == Kmer::Sentinel) {
+                    break;
+                }
+                filter.insert(&MinimizerNgram(ngram));
+            }
+        }
+
+        Self { filters }
+    }
+}
+
+impl BloomFilterQuery {
+    /// Returns false if the query definitely does not occur in the reference.
+    fn may_contain(&self, query_super_kmers: &[SuperKmer]) -> bool {
+        let n = query_super_kmers.len().min(BLOOM_FILTER_MAX_NGRAM);
+        let filter = &self.filters[n - 1];
+        query_super_kmers
+            .windows(n)
+            .all(|ngram| filter.contains(&MinimizerNgram(ngram)))
     }
 }
 
+/// Computes the LCP array of `suffix_array` with Kasai et al.'s algorithm, where super-k-mers
+/// i and j match if `same_minimizer(i, j)`. Suffixes that are adjacent in the text share all but
+/// the first super-k-mer of their common prefix with their predecessors in the suffix array, so
+/// the common prefix shrinks by at most one from one text position to the next.
+fn kasai_lcp(suffix_array: &[usize], same_minimizer: impl Fn(usize, usize) -> bool) -> Vec<usize> {
+    let n = suffix_array.len();
+    let mut rank = vec![0; n];
+    for (i, &suffix) in suffix_array.iter().enumerate() {
+        rank[suffix] = i;
+    }
+
+    let mut lcp = vec![0; n];
+    let mut h = 0;
+    for suffix in 0..n {
+        if rank[suffix] == 0 {
+            h = 0;
+            continue;
+        }
+        let previous = suffix_array[rank[suffix] - 1];
+        // The sentinel is unique, so the loop stops before either suffix runs out
+        while same_minimizer(suffix + h, previous + h) {
+            h += 1;
+        }
+        lcp[rank[suffix]] = h;
+        h = h.saturating_sub(1);
+    }
+
+    lcp
+}
+
 impl<T: QueryMode> SuffixArray<T> {
     // put any methods that don't need to touch query_mode_aux_data here
 
-    pub fn from_kmers(mut kmers: KmerSequence, w: usize, o: MinimizerOrder, init_params: T::InitParams) -> Self {
+    #[cfg(test)]
+    pub fn from_kmers(kmers: KmerSequence, w: usize, o: MinimizerOrder, init_params: T::InitParams) -> Self {
+        Self::from_kmers_seeded(kmers, w, o, 0, init_params)
+    }
+
+    /// Like `from_kmers`, but with the seed used to rank k-mers under the hash-based orders.
+    pub fn from_kmers_seeded(mut kmers: KmerSequence, w: usize, o: MinimizerOrder, hash_seed: u64, init_params: T::InitParams) -> Self {
         // Generate occurrence HashMap.
         if o == MinimizerOrder::Occurrence {
             kmers.generate_occ();
         }
         let kmers = kmers; // Drop mutability.
+        assert!(
+            !o.uses_hash() || kmers.kmers_fit_in_u128(),
+            "hash-based minimizer orders require k-mers that can be mapped to u128 integers"
+        );
+        let (super_kmers, num_minimizer_positions) =
+            kmers.compute_super_kmers_counting_positions(w, o, hash_seed, None).unwrap();
+        Self {
+            num_minimizer_positions: Some(num_minimizer_positions),
+            ..Self::from_super_kmers(kmers, super_kmers, w, o, hash_seed, None, init_params)
+        }
+    }
+
+    /// Builds the suffix array over the syncmers of `kmers` instead of its minimizers.
+    pub fn from_kmers_syncmers(kmers: KmerSequence, params: SyncmerParams, init_params: T::InitParams) -> Self {
+        let super_kmers = kmers.compute_syncmers(&params);
+        Self::from_super_kmers(kmers, super_kmers, 1, MinimizerOrder::Lexicographic, 0, Some(params), init_params)
+    }
+
+    fn from_super_kmers(
+        kmers: KmerSequence,
+        mut super_kmers: Vec<SuperKmer>,
+        w: usize,
+        o: MinimizerOrder,
+        hash_seed: u64,
+        syncmers: Option<SyncmerParams>,
+        init_params: T::InitParams,
+    ) -> Self {
         // Construct the suffix array
-        let mut super_kmers = kmers.compute_super_kmers(w, o, None).unwrap();
         // Push sentinel kmer.
         super_kmers.push(SuperKmer {
             start_pos: kmers.get_original_string_len(),
@@ -125,17 +312,28 @@ impl<T: QueryMode> SuffixArray<T> {
 
         let n = super_kmers.len();
 
-        let mut suffix_array = (0..n).collect::<Vec<_>>();
-
-        // Sort the suffix array
-        suffix_array.sort_by(|&i1, &i2| {
-            let suffix1 = &super_kmers[i1..n];
-            let suffix2 = &super_kmers[i2..n];
-
-            suffix1.iter().my_cmp_by(suffix2.iter(), |x, y| {
-                kmers.compare_kmers(&x.minimizer, &y.minimizer)
-            })
+        // Rank the minimizers, so that suffixes compare like their rank sequences. The sentinel
+        // is the greatest minimizer, and is followed by a smaller terminator for SA-IS. As the
+        // sentinel only occurs once, the terminator never decides a comparison. Equal minimizers
+        // get equal ranks, so the ranks don't depend on how the parallel sort orders them.
+        let mut by_minimizer: Vec<usize> = (0..n).collect();
+        by_minimizer.par_sort_unstable_by(|&i1, &i2| {
+            kmers.compare_kmers(&super_kmers[i1].minimizer, &super_kmers[i2].minimizer)
         });
+        let mut ranks = vec![0; n + 1];
+        let mut rank = 1;
+        for (j, &i) in by_minimizer.iter().enumerate() {
+            if j > 0
+                && kmers.compare_kmers(&super_kmers[by_minimizer[j - 1]].minimizer, &super_kmers[i].minimizer)
+                    != Ordering::Equal
+            {
+                rank += 1;
+            }
+            ranks[i] = rank;
+        }
+
+        // Sort the suffix array, dropping the terminator's suffix which comes first
+        let suffix_array = sais::suffix_array(&ranks, rank + 1).split_off(1);
 
         let suffix_array_slices: Vec<_> =
             suffix_array.iter().map(|&i| &super_kmers[i..n]).collect();
@@ -143,16 +341,45 @@ impl<T: QueryMode> SuffixArray<T> {
         let query_mode_aux_data =
             T::initialize_aux_data(&kmers, w, &suffix_array_slices, init_params);
 
+        // The sentinel is the greatest minimizer, and isn't stored in the table
+        let minimizers = MinimizerTable::new(
+            by_minimizer[..n - 1]
+                .iter()
+                .dedup_by(|&&i1, &&i2| ranks[i1] == ranks[i2])
+                .map(|&i| super_kmers[i].minimizer.clone())
+                .collect(),
+        );
+        let (super_kmers, suffix_array) = if T::STORES_SUFFIX_ARRAY {
+            let ranks: Vec<usize> = ranks[..n].iter().map(|rank| rank - 1).collect();
+            (PackedSuperKmers::new(&super_kmers, Some(&ranks), minimizers), suffix_array)
+        } else {
+            (PackedSuperKmers::new(&super_kmers, None, minimizers), Vec::new())
+        };
+
         Self {
+            name: String::new(),
             underlying_kmers: kmers,
             w,
             minimizer_order: o,
+            hash_seed,
+            syncmers,
+            num_minimizer_positions: None,
             super_kmers,
             suffix_array,
+            lcp: None,
             query_mode_aux_data,
         }
     }
 
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn get_underlying_kmers(&self) -> &KmerSequence {
         &self.underlying_kmers
     }
@@ -160,65 +387,114 @@ impl<T: QueryMode> SuffixArray<T> {
     pub fn w(&self) -> usize {
         self.w
     }
-}
 
-// The ground truth query mode which performs an extremely inefficient query for testing purposes.
-impl Queryable for SuffixArray<GroundTruthQuery> {
-    fn query(&self, query: &[u8]) -> (Vec<usize>, usize) {
-        let ref_str = self.underlying_kmers.get_original_string();
-        let mut result = Vec::new();
-        for (i, window) in ref_str.windows(query.len()).enumerate() {
-            if query == window {
-                //return (Some(i), false);
-                result.push(i);
-            }
+    /// The number of super-k-mers, excluding the sentinel.
+    pub fn num_super_kmers(&self) -> usize {
+        self.super_kmers.len() - 1
+    }
+
+    /// The number of super-k-mers if windows with equal minimizers at different positions
+    /// weren't merged, or None if the suffix array samples syncmers.
+    pub fn num_minimizer_positions(&self) -> Option<usize> {
+        self.num_minimizer_positions
+    }
+
+    /// Computes and stores the LCP array (see `lcp`), if the suffix array is stored.
+    pub fn with_lcp(mut self) -> Self {
+        self.lcp = self.compute_lcp();
+        self
+    }
+
+    /// The LCP array, measured in super-k-mers, if it was computed when building the index.
+    pub fn lcp(&self) -> Option<&[usize]> {
+        self.lcp.as_deref()
+    }
+
+    /// Computes the LCP array (see `kasai_lcp`), or returns None if the query mode doesn't
+    /// store the suffix array.
+    pub fn compute_lcp(&self) -> Option<Vec<usize>> {
+        if !T::STORES_SUFFIX_ARRAY {
+            return None;
         }
-        //(None, false)
-        (result, 0)
+        Some(kasai_lcp(&self.suffix_array, |i, j| {
+            self.super_kmers.minimizer_rank(i) == self.super_kmers.minimizer_rank(j)
+        }))
     }
-}
 
-// The standard query mode, with no accelerant data structures
-impl Queryable for SuffixArray<StandardQuery> {
-    fn query(&self, query: &[u8]) -> (Vec<usize>, usize) {
+    /// Checks that `query` can be searched for, i.e. that it is at least w + k - 1 long and
+    /// only contains characters from the reference alphabet. Queries failing the latter
+    /// check are still safe to search for, but never match.
+    pub fn validate_query(&self, query: &[u8]) -> Result<()> {
+        let min_len = self.w + self.underlying_kmers.k() - 1;
+        if query.len() < min_len {
+            bail!("query length {} is shorter than the minimum length w + k - 1 = {}", query.len(), min_len);
+        }
+
+        let alphabet = self.underlying_kmers.get_alphabet();
+        if alphabet.masks_unknown() {
+            return Ok(());
+        }
+        if let Some(&b) = query.iter().find(|&&b| alphabet.encode(b).is_none()) {
+            bail!("query contains {:?}, which does not occur in the reference", b as char);
+        }
+
+        Ok(())
+    }
+
+    /// Transforms a query into minimizer space, using the minimizer order of the reference.
+    /// Returns None if the query can't occur in the reference.
+    fn query_super_kmers(&self, query: &[u8]) -> Option<Vec<SuperKmer>> {
         assert!(
             query.len() >= self.w + self.underlying_kmers.k() - 1,
             "query length was shorter than minimum length required by w + k - 1"
         );
 
-        let query_kmers = KmerSequence::from_bytes(
-            query,
-            self.underlying_kmers.k(),
-            self.underlying_kmers.alphabet(),
-        );
-        let query_super_kmers = query_kmers.compute_super_kmers(self.w, self.minimizer_order, Some(&self.underlying_kmers));
-        let Some(query_super_kmers) = query_super_kmers else { return (Vec::new(), 0) };
-
-        let cmp_slice_to_query = |slice: &[SuperKmer]| {
-            //let l = cmp::min(slice.len(), query_super_kmers.len());
-            let l = query_super_kmers.len();
-            slice.iter().take(l).my_cmp_by(
-                query_super_kmers.iter().take(l),
-                |SuperKmer {
-                     minimizer: minimizer1,
-                     ..
-                 },
-                 SuperKmer {
-                     minimizer: minimizer2,
-                     ..
-                 }| self.underlying_kmers.compare_kmers(minimizer1, minimizer2),
-            )
+        // A query containing a character absent from the reference can't occur in it
+        let query_kmers = if self.underlying_kmers.is_canonical() {
+            KmerSequence::from_bytes_canonical(
+                query,
+                self.underlying_kmers.k(),
+                self.underlying_kmers.alphabet(),
+            )?
+        } else {
+            KmerSequence::from_bytes(
+                query,
+                self.underlying_kmers.k(),
+                self.underlying_kmers.alphabet(),
+            )?
+        };
+        let query_super_kmers = match &self.syncmers {
+            Some(params) => Some(query_kmers.compute_syncmers(params)),
+            None => query_kmers.compute_super_kmers(
+                self.w,
+                self.minimizer_order,
+                self.hash_seed,
+                Some(&self.underlying_kmers),
+            ),
         };
+        // A query made up entirely of masked windows, or lying in a gap between syncmers,
+        // has nothing to search for
+        query_super_kmers.filter(|query_super_kmers| !query_super_kmers.is_empty())
+    }
+
+    /// Finds the range of `suffix_array` (a slice of the full suffix array) whose suffixes
+    /// start with the query's minimizers.
+    fn search(&self, suffix_array: &[usize], query_super_kmers: &[SuperKmer]) -> (usize, usize) {
+        let query_keys = self.super_kmers.query_keys(&self.underlying_kmers, query_super_kmers);
+        let cmp_suffix_to_query = |s: usize| self.super_kmers.compare_suffix(s, &query_keys, 0).1;
 
         // Look for first index in suffix array == kmer
-        let left_idx = self.suffix_array.partition_point(|&s| {
-            cmp_slice_to_query(&self.super_kmers[s..self.super_kmers.len()]) == Ordering::Less
-        });
+        let left_idx = suffix_array.partition_point(|&s| cmp_suffix_to_query(s) == Ordering::Less);
         // Look for first index in suffix array > kmer
-        let right_idx = self.suffix_array.partition_point(|&s| {
-            cmp_slice_to_query(&self.super_kmers[s..self.super_kmers.len()]) != Ordering::Greater
-        });
+        let right_idx = suffix_array.partition_point(|&s| cmp_suffix_to_query(s) != Ordering::Greater);
 
+        (left_idx, right_idx)
+    }
+
+    /// Checks each candidate in `suffix_array[left_idx..right_idx]` against the original
+    /// string, returning the matching positions and the number of false positives.
+    fn verify(&self, candidates: &Candidates, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let Candidates { suffix_array, range: (left_idx, right_idx), query_super_kmers } = candidates;
         if left_idx == right_idx {
             // Query not present
             //return (None, false);
@@ -228,42 +504,497 @@ impl Queryable for SuffixArray<StandardQuery> {
         // Query could be present anywhere in the range
         let mut result = Vec::new();
         let mut false_positives = 0;
-        let original_string = self.underlying_kmers.get_original_string();
-        for i in left_idx..right_idx {
-            let super_kmers = &self.super_kmers[self.suffix_array[i]..self.super_kmers.len()]
-                [0..query_super_kmers.len()];
-
-            let first_super_kmer = super_kmers.first().unwrap();
-            let last_super_kmer = super_kmers.last().unwrap();
-            let start_pos = first_super_kmer.start_pos;
-            let end_pos = last_super_kmer.start_pos + last_super_kmer.length;
-
+        let reference = self.underlying_kmers.get_reference();
+        let alphabet = self.underlying_kmers.get_alphabet();
+        let (leading, trailing) = Self::unsampled_ends(query, query_super_kmers);
+        let mut candidate = Vec::new();
+        for &suffix in &suffix_array[*left_idx..*right_idx] {
+            let last = suffix + query_super_kmers.len() - 1;
+            let start_pos = self.super_kmers.start_pos(suffix).saturating_sub(leading);
+            let end_pos = (self.super_kmers.start_pos(last) + self.super_kmers.length(last) + trailing)
+                .min(reference.len());
+            reference.decode_into(start_pos..end_pos, &mut candidate);
+
+            // A single candidate range may contain several occurrences (e.g. tandem repeats)
             let mut found = false;
-            for (i, w) in original_string[start_pos..end_pos]
+            for (i, w) in candidate
                 .windows(query.len())
                 .enumerate()
             {
-                if w == query {
-                    //return (Some(start_pos + i), false);
+                if alphabet.bytes_eq(w, query) {
                     found = true;
                     result.push(start_pos + i);
-                    break;
+                    if mode == LocateMode::First {
+                        return (result, false_positives);
+                    }
                 }
             }
 
-            if found == false {
+            if !found {
                 false_positives += 1;
             }
         }
 
+        // Candidate ranges can overlap, reporting the same occurrence more than once
+        result.sort_unstable();
+        result.dedup();
+
         //(None, true)
         (result, false_positives)
     }
+
+    /// Counts the candidates, or the verified occurrences among them, without collecting
+    /// positions.
+    fn count_candidates(&self, candidates: &Candidates, query: &[u8], verified: bool) -> usize {
+        let Candidates { suffix_array, range: (left_idx, right_idx), .. } = candidates;
+        if !verified {
+            return right_idx - left_idx;
+        }
+
+        // Every occurrence has its first sampled window inside the first super-k-mer of
+        // exactly one candidate, so counting only those windows never counts an occurrence twice.
+        let window_len = self.w + self.underlying_kmers.k() - 1;
+        let reference = self.underlying_kmers.get_reference();
+        let alphabet = self.underlying_kmers.get_alphabet();
+        let (leading, _) = Self::unsampled_ends(query, &candidates.query_super_kmers);
+        let mut candidate = Vec::new();
+        suffix_array[*left_idx..*right_idx]
+            .iter()
+            .map(|&suffix| {
+                let (start_pos, length) = (self.super_kmers.start_pos(suffix), self.super_kmers.length(suffix));
+                // Occurrences can't start before the reference
+                let num_windows = (length + 1 - window_len).saturating_sub(leading.saturating_sub(start_pos));
+                let first = start_pos.saturating_sub(leading);
+                let end = (first + num_windows + query.len() - 1).min(reference.len());
+                reference.decode_into(first..end, &mut candidate);
+                candidate
+                    .windows(query.len())
+                    .filter(|&window| alphabet.bytes_eq(window, query))
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Finds the occurrences of a query that contains no syncmer, which can only lie in the
+    /// gaps between the reference's syncmers. Minimizer-based suffix arrays sample every
+    /// valid query, so this never finds anything for them.
+    fn locate_in_gaps(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        if self.syncmers.is_none() {
+            return (Vec::new(), 0);
+        }
+
+        let k = self.underlying_kmers.k();
+        let reference = self.underlying_kmers.get_reference();
+        let alphabet = self.underlying_kmers.get_alphabet();
+        let num_kmers = reference.len() + 1 - k;
+
+        // An occurrence at p contains no syncmer iff none starts in [p, p + query.len() - k]
+        let mut result = Vec::new();
+        let mut gap_start = 0;
+        let syncmer_starts = (0..self.super_kmers.len() - 1).map(|i| self.super_kmers.start_pos(i));
+        for next_syncmer in syncmer_starts.chain([num_kmers]) {
+            let gap_end = (next_syncmer + k).saturating_sub(query.len());
+            if gap_start < gap_end {
+                let gap = reference.decode(gap_start..gap_end + query.len() - 1);
+                for (i, window) in gap.windows(query.len()).enumerate() {
+                    if alphabet.bytes_eq(window, query) {
+                        result.push(gap_start + i);
+                        if mode == LocateMode::First {
+                            return (result, 0);
+                        }
+                    }
+                }
+            }
+            gap_start = next_syncmer + 1;
+        }
+
+        (result, 0)
+    }
+
+    /// The number of query characters before the first super-k-mer and after the last one.
+    /// With minimizers, these are only non-zero when the query starts or ends with masked
+    /// windows. With syncmers, they are also non-zero whenever the query doesn't start with a
+    /// syncmer or end with one.
+    fn unsampled_ends(query: &[u8], query_super_kmers: &[SuperKmer]) -> (usize, usize) {
+        let first = query_super_kmers.first().unwrap();
+        let last = query_super_kmers.last().unwrap();
+        (first.start_pos, query.len() - (last.start_pos + last.length))
+    }
+}
+
+// The ground truth query mode which performs an extremely inefficient query for testing purposes.
+impl Queryable for SuffixArray<GroundTruthQuery> {
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let reference = self.underlying_kmers.get_reference();
+        let ref_str = reference.decode(0..reference.len());
+        let alphabet = self.underlying_kmers.get_alphabet();
+        let mut result = Vec::new();
+        for (i, window) in ref_str.windows(query.len()).enumerate() {
+            if alphabet.bytes_eq(query, window) {
+                //return (Some(i), false);
+                result.push(i);
+                if mode == LocateMode::First {
+                    break;
+                }
+            }
+        }
+        //(None, false)
+        (result, 0)
+    }
+
+    fn count(&self, query: &[u8], _verified: bool) -> usize {
+        let reference = self.underlying_kmers.get_reference();
+        let ref_str = reference.decode(0..reference.len());
+        let alphabet = self.underlying_kmers.get_alphabet();
+        ref_str
+            .windows(query.len())
+            .filter(|&window| alphabet.bytes_eq(query, window))
+            .count()
+    }
+}
+
+impl SuffixArray<StandardQuery> {
+    fn candidates(&self, query: &[u8]) -> Option<Candidates<'_>> {
+        let query_super_kmers = self.query_super_kmers(query)?;
+
+        let range = self.search(&self.suffix_array, &query_super_kmers);
+        Some(Candidates { suffix_array: &self.suffix_array, range, query_super_kmers })
+    }
+}
+
+impl SuffixArray<LcpQuery> {
+    fn candidates(&self, query: &[u8]) -> Option<Candidates<'_>> {
+        let query_super_kmers = self.query_super_kmers(query)?;
+
+        let query_keys = self.super_kmers.query_keys(&self.underlying_kmers, &query_super_kmers);
+        let range = (self.lcp_search(&query_keys, false), self.lcp_search(&query_keys, true));
+        Some(Candidates { suffix_array: &self.suffix_array, range, query_super_kmers })
+    }
+
+    /// Finds the first index of the suffix array whose suffix is greater than the query's
+    /// minimizers (given by their keys, see `PackedSuperKmers::query_keys`), or greater or equal
+    /// to them if `!past_equal`, comparing at most as many super-k-mers as the query has.
+    fn lcp_search(&self, query_keys: &[usize], past_equal: bool) -> usize {
+        // Compares the query with the suffix at `suffix_array[i]`, knowing that they share
+        // the first `start` super-k-mers. Returns their LCP and whether the suffix comes
+        // after the query's position in the suffix array.
+        let compare_from = |i: usize, start: usize| {
+            match self.super_kmers.compare_suffix(self.suffix_array[i], query_keys, start) {
+                (h, Ordering::Equal) => (h, !past_equal),
+                (h, ordering) => (h, ordering == Ordering::Greater),
+            }
+        };
+
+        let (mut l, goes_right) = compare_from(0, 0);
+        if goes_right {
+            return 0;
+        }
+        // The sentinel suffix is the last and greatest one
+        let (mut left, mut right) = (0, self.suffix_array.len() - 1);
+        let mut r = 0;
+
+        // Invariant: the suffix at `left` comes before the query's position and the one at
+        // `right` after it, sharing `l` and `r` super-k-mers with the query respectively
+        let LcpQuery { llcp, rlcp } = &self.query_mode_aux_data;
+        while right - left > 1 {
+            let mid = (left + right) / 2;
+            // The suffix at mid shares min(l, llcp[mid]) super-k-mers with the query, and if
+            // llcp[mid] != l it differs from the query where it differs from the suffix at
+            // left (or vice versa), so the order follows without comparing. Likewise for r.
+            let (h, goes_right) = if l >= r {
+                match llcp[mid].cmp(&l) {
+                    Ordering::Greater => (l, false),
+                    Ordering::Less => (llcp[mid], true),
+                    Ordering::Equal => compare_from(mid, l),
+                }
+            } else {
+                match rlcp[mid].cmp(&r) {
+                    Ordering::Greater => (r, true),
+                    Ordering::Less => (rlcp[mid], false),
+                    Ordering::Equal => compare_from(mid, r),
+                }
+            };
+            if goes_right {
+                right = mid;
+                r = h;
+            } else {
+                left = mid;
+                l = h;
+            }
+        }
+
+        right
+    }
+}
+
+// The LCP-accelerated query mode, which skips super-k-mers already known to match
+impl Queryable for SuffixArray<LcpQuery> {
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let Some(candidates) = self.candidates(query) else { return self.locate_in_gaps(query, mode) };
+        self.verify(&candidates, query, mode)
+    }
+
+    fn count(&self, query: &[u8], verified: bool) -> usize {
+        let Some(candidates) = self.candidates(query) else {
+            return self.locate_in_gaps(query, LocateMode::All).0.len();
+        };
+        self.count_candidates(&candidates, query, verified)
+    }
+}
+
+impl SuffixArray<FmIndexQuery> {
+    /// Searches the FM-index, returning the candidates' positions in the minimizer sequence
+    /// as their suffix array.
+    fn candidates(&self, query: &[u8]) -> Option<(Vec<usize>, Vec<SuperKmer>)> {
+        let query_super_kmers = self.query_super_kmers(query)?;
+
+        let fm_index = &self.query_mode_aux_data;
+        let (left, right) = fm_index.search(self.super_kmers.minimizers(), &self.underlying_kmers, &query_super_kmers);
+        let positions = (left..right).map(|i| fm_index.locate(i)).collect();
+        Some((positions, query_super_kmers))
+    }
+}
+
+// The FM-index query mode, which searches the Burrows-Wheeler transform instead of the suffix array
+impl Queryable for SuffixArray<FmIndexQuery> {
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let Some((positions, query_super_kmers)) = self.candidates(query) else {
+            return self.locate_in_gaps(query, mode);
+        };
+        let candidates = Candidates { suffix_array: &positions, range: (0, positions.len()), query_super_kmers };
+        self.verify(&candidates, query, mode)
+    }
+
+    fn count(&self, query: &[u8], verified: bool) -> usize {
+        if !verified {
+            // Counting candidates doesn't need their positions
+            let Some(query_super_kmers) = self.query_super_kmers(query) else {
+                return self.locate_in_gaps(query, LocateMode::All).0.len();
+            };
+            let (left, right) = self.query_mode_aux_data.search(self.super_kmers.minimizers(), &self.underlying_kmers, &query_super_kmers);
+            return right - left;
+        }
+
+        let Some((positions, query_super_kmers)) = self.candidates(query) else {
+            return self.locate_in_gaps(query, LocateMode::All).0.len();
+        };
+        let candidates = Candidates { suffix_array: &positions, range: (0, positions.len()), query_super_kmers };
+        self.count_candidates(&candidates, query, verified)
+    }
+}
+
+// The standard query mode, with no accelerant data structures
+impl Queryable for SuffixArray<StandardQuery> {
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let Some(candidates) = self.candidates(query) else { return self.locate_in_gaps(query, mode) };
+        self.verify(&candidates, query, mode)
+    }
+
+    fn count(&self, query: &[u8], verified: bool) -> usize {
+        let Some(candidates) = self.candidates(query) else {
+            return self.locate_in_gaps(query, LocateMode::All).0.len();
+        };
+        self.count_candidates(&candidates, query, verified)
+    }
+}
+
+impl SuffixArray<BloomFilterQuery> {
+    /// Returns None only if the query has no super-k-mers to search for. A query rejected by
+    /// the bloom filters has no candidates, and as it does have super-k-mers, it can't lie in
+    /// a gap between syncmers either.
+    fn candidates(&self, query: &[u8]) -> Option<Candidates<'_>> {
+        let query_super_kmers = self.query_super_kmers(query)?;
+
+        if !self.query_mode_aux_data.may_contain(&query_super_kmers) {
+            // Query not present
+            return Some(Candidates { suffix_array: &[], range: (0, 0), query_super_kmers });
+        }
+
+        let range = self.search(&self.suffix_array, &query_super_kmers);
+        Some(Candidates { suffix_array: &self.suffix_array, range, query_super_kmers })
+    }
+}
+
+// The bloom filter query mode, which rejects queries absent from the reference before searching
+impl Queryable for SuffixArray<BloomFilterQuery> {
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let Some(candidates) = self.candidates(query) else { return self.locate_in_gaps(query, mode) };
+        self.verify(&candidates, query, mode)
+    }
+
+    fn count(&self, query: &[u8], verified: bool) -> usize {
+        let Some(candidates) = self.candidates(query) else {
+            return self.locate_in_gaps(query, LocateMode::All).0.len();
+        };
+        self.count_candidates(&candidates, query, verified)
+    }
+}
+
+/// Manber and Myers' LCP-accelerated binary search. Every search halves the suffix array in
+/// the same way, starting from the interval [0, n - 1], so each midpoint M is reached from a
+/// single interval [L, R] and the LCPs of its suffix with those of L and R can be precomputed.
+/// Comparisons then resume after the prefix the query is known to share with M, rather than
+/// re-walking it from the first super-k-mer.
+#[derive(Encode, Decode)]
+pub struct LcpQuery {
+    /// `llcp[M]` is the LCP of the suffixes at L and M, in super-k-mers
+    llcp: Vec<usize>,
+    /// `rlcp[M]` is the LCP of the suffixes at M and R, in super-k-mers
+    rlcp: Vec<usize>,
+}
+
+impl QueryMode for LcpQuery {
+    type InitParams = ();
+
+    fn initialize_aux_data(
+        kmers: &KmerSequence,
+        _w: usize,
+        suffix_array: &[&[SuperKmer]],
+        _init_params: Self::InitParams,
+    ) -> Self {
+        // Every suffix ends with the sentinel, so the longest one is the whole text
+        let n = suffix_array.len();
+        let super_kmers = *suffix_array.iter().find(|suffix| suffix.len() == n).unwrap();
+        let positions: Vec<usize> = suffix_array.iter().map(|suffix| n - suffix.len()).collect();
+        let lcp = kasai_lcp(&positions, |i, j| {
+            kmers.compare_kmers(&super_kmers[i].minimizer, &super_kmers[j].minimizer) == Ordering::Equal
+        });
+
+        let mut aux_data = Self { llcp: vec![0; n], rlcp: vec![0; n] };
+        if n > 1 {
+            aux_data.fill(&lcp, 0, n - 1);
+        }
+        aux_data
+    }
+}
+
+impl LcpQuery {
+    /// Fills in the midpoints below the interval [left, right], returning the LCP of the
+    /// suffixes at its ends, i.e. the minimum of `lcp[left + 1..=right]`.
+    fn fill(&mut self, lcp: &[usize], left: usize, right: usize) -> usize {
+        if right - left == 1 {
+            return lcp[right];
+        }
+        let mid = (left + right) / 2;
+        self.llcp[mid] = self.fill(lcp, left, mid);
+        self.rlcp[mid] = self.fill(lcp, mid, right);
+        self.llcp[mid].min(self.rlcp[mid])
+    }
+}
+
+/// An FM-index over the minimizer sequence, replacing the suffix array and the minimizer ranks
+/// of the super-k-mers. Each super-k-mer is represented by the rank of its minimizer among the
+/// distinct minimizers (see `PackedSuperKmers::minimizers`), with the sentinel ranked last. As the sentinel is unique, sorting the
+/// rotations of this text sorts its suffixes, so the Burrows-Wheeler transform supports
+/// backward search over the same intervals as the suffix array.
+///
+/// Only the suffix array entries of every s-th text position are kept, where s is the sample
+/// rate given at construction. Locating any other entry walks back through the text with the
+/// LF mapping until it reaches a sampled position, taking at most s - 1 steps, so the rate
+/// trades the space of the samples against the time to locate each candidate.
+#[derive(Encode, Decode)]
+pub struct FmIndexQuery {
+    /// The Burrows-Wheeler transform of the minimizer ranks
+    bwt: WaveletMatrix,
+    /// `counts.get(c)` is the number of super-k-mers with minimizer ranks less than c
+    counts: EliasFano,
+    /// Marks the suffix array entries whose text positions are multiples of the sample rate
+    sampled: RankBitVec,
+    /// The marked suffix array entries, in suffix array order
+    samples: PackedInts,
+}
+
+impl QueryMode for FmIndexQuery {
+    /// The suffix array sample rate
+    type InitParams = usize;
+
+    const STORES_SUFFIX_ARRAY: bool = false;
+
+    fn initialize_aux_data(
+        kmers: &KmerSequence,
+        _w: usize,
+        suffix_array: &[&[SuperKmer]],
+        sample_rate: Self::InitParams,
+    ) -> Self {
+        assert!(sample_rate > 0, "suffix array sample rate must be positive");
+
+        // Every suffix ends with the sentinel, so the longest one is the whole text
+        let n = suffix_array.len();
+        let super_kmers = *suffix_array.iter().find(|suffix| suffix.len() == n).unwrap();
+
+        // The same ranks as in the suffix array's table of distinct minimizers
+        let mut minimizers: Vec<&Kmer> = super_kmers[..n - 1].iter().map(|super_kmer| &super_kmer.minimizer).collect();
+        minimizers.sort_unstable_by(|x, y| kmers.compare_kmers(x, y));
+        minimizers.dedup_by(|x, y| kmers.compare_kmers(x, y) == Ordering::Equal);
+        let text: Vec<usize> = super_kmers
+            .iter()
+            .map(|super_kmer| match super_kmer.minimizer {
+                Kmer::Sentinel => minimizers.len(),
+                ref minimizer => minimizers.binary_search_by(|x| kmers.compare_kmers(x, minimizer)).unwrap(),
+            })
+            .collect();
+        let alphabet_size = minimizers.len() + 1;
+
+        // The character before each suffix, wrapping around to the sentinel for the first one
+        let bwt: Vec<usize> = suffix_array.iter().map(|suffix| text[(2 * n - 1 - suffix.len()) % n]).collect();
+
+        let mut counts = vec![0; alphabet_size + 1];
+        for &c in &text {
+            counts[c + 1] += 1;
+        }
+        for c in 0..alphabet_size {
+            counts[c + 1] += counts[c];
+        }
+
+        let positions = suffix_array.iter().map(|suffix| n - suffix.len());
+        let samples: Vec<usize> = positions.clone().filter(|i| i % sample_rate == 0).collect();
+        Self {
+            bwt: WaveletMatrix::new(&bwt, alphabet_size),
+            counts: EliasFano::from_values(counts.into_iter()),
+            sampled: RankBitVec::from_bits(positions.map(|i| i % sample_rate == 0)),
+            samples: PackedInts::from_values(samples.into_iter()),
+        }
+    }
+}
+
+impl FmIndexQuery {
+    /// Maps the suffix array entry of a suffix to that of the suffix one super-k-mer longer.
+    fn lf(&self, i: usize) -> usize {
+        let (c, rank) = self.bwt.get_and_rank(i);
+        self.counts.get(c) + rank
+    }
+
+    /// Finds the suffix array interval of the suffixes starting with the query's minimizers by
+    /// backward search, ranking them in the reference's distinct `minimizers`.
+    fn search(&self, minimizers: &MinimizerTable, kmers: &KmerSequence, query_super_kmers: &[SuperKmer]) -> (usize, usize) {
+        let (mut left, mut right) = (0, self.counts.get(self.counts.len() - 1));
+        for super_kmer in query_super_kmers.iter().rev() {
+            // Query minimizers absent from the reference can't match
+            let Ok(c) = minimizers.search(kmers, &super_kmer.minimizer) else { return (0, 0) };
+            left = self.counts.get(c) + self.bwt.rank(c, left);
+            right = self.counts.get(c) + self.bwt.rank(c, right);
+            if left == right {
+                break;
+            }
+        }
+        (left, right)
+    }
+
+    /// The text position of suffix array entry `i`, found by walking backwards through the
+    /// text to a sampled position.
+    fn locate(&self, mut i: usize) -> usize {
+        let mut steps = 0;
+        while !self.sampled.get(i) {
+            i = self.lf(i);
+            steps += 1;
+        }
+        self.samples.get(self.sampled.rank1(i)) + steps
+    }
 }
 
 #[derive(Encode, Decode)]
 pub struct PWLLearnedQuery {
-    // TODO: find a more efficient way to do lookups among the segments?
+    /// Sorted, contiguous segments (see `find_segment`)
     #[bincode(with_serde)]
     plr_begin_segments: Vec<plr::Segment>,
     #[bincode(with_serde)]
@@ -284,6 +1015,10 @@ impl QueryMode for PWLLearnedQuery {
         init_params: Self::InitParams,
     ) -> Self {
         let gamma: f64 = init_params;
+        assert!(
+            kmers.kmers_fit_in_u128(),
+            "PWLLearnedQuery requires k-mers that can be mapped to u128 integers"
+        );
 
         let sa_len = suffix_array.len();
         let mut suffix_array = suffix_array.into_iter().enumerate();
@@ -340,6 +1075,9 @@ impl QueryMode for PWLLearnedQuery {
         if let Some(segment) = plr_end.finish() {
             plr_end_segments.push(segment);
         }
+        for segments in [&plr_begin_segments, &plr_end_segments] {
+            assert!(segments.windows(2).all(|pair| pair[0].stop == pair[1].start));
+        }
 
         Self {
             plr_begin_segments,
@@ -349,35 +1087,17 @@ impl QueryMode for PWLLearnedQuery {
     }
 }
 
-impl Queryable for SuffixArray<PWLLearnedQuery> {
-    fn query(&self, query: &[u8]) -> (Vec<usize>, usize) {
-        assert!(
-            query.len() >= self.w + self.underlying_kmers.k() - 1,
-            "query length was shorter than minimum length required by w + k - 1"
-        );
+/// Finds the segment whose [start, stop) range holds `x` by binary search. Greedy PLR
+/// segments are contiguous, each stopping where the next starts, so `x` is in the first
+/// segment that stops after it, if any.
+fn find_segment(segments: &[plr::Segment], x: f64) -> Option<&plr::Segment> {
+    let i = segments.partition_point(|segment| segment.stop <= x);
+    segments.get(i).filter(|segment| segment.start <= x)
+}
 
-        let query_kmers = KmerSequence::from_bytes(
-            query,
-            self.underlying_kmers.k(),
-            self.underlying_kmers.alphabet(),
-        );
-        let query_super_kmers = query_kmers.compute_super_kmers(self.w, self.minimizer_order, Some(&self.underlying_kmers));
-        let Some(query_super_kmers) = query_super_kmers else { return (Vec::new(), 0) };
-
-        let cmp_slice_to_query = |slice: &[SuperKmer]| {
-            let l = query_super_kmers.len();
-            slice.iter().take(l).my_cmp_by(
-                query_super_kmers.iter().take(l),
-                |SuperKmer {
-                     minimizer: minimizer1,
-                     ..
-                 },
-                 SuperKmer {
-                     minimizer: minimizer2,
-                     ..
-                 }| self.underlying_kmers.compare_kmers(minimizer1, minimizer2),
-            )
-        };
+impl SuffixArray<PWLLearnedQuery> {
+    fn candidates(&self, query: &[u8]) -> Option<Candidates<'_>> {
+        let query_super_kmers = self.query_super_kmers(query)?;
 
         let sa_len = self.suffix_array.len();
 
@@ -390,19 +1110,21 @@ impl Queryable for SuffixArray<PWLLearnedQuery> {
             .kmer_to_integer(&query_super_kmers.first().unwrap().minimizer);
         let lookup = |segments: &[plr::Segment], x: u128| {
             let x = x as f64;
-            let segment = segments
-                .iter()
-                .find(|&segment| segment.start <= x && x < segment.stop)
-                .unwrap();
-            segment.slope * x + segment.intercept
+            let segment = find_segment(segments, x)?;
+            Some(segment.slope * x + segment.intercept)
         };
-        // Compute bounds from PWL function
-        let left_bound = lookup(&self.query_mode_aux_data.plr_begin_segments, first_kmer) as usize;
-        let right_bound =
-            lookup(&self.query_mode_aux_data.plr_end_segments, first_kmer).ceil() as usize;
-        // Account for error and clamp accordingly
-        let left_bound = left_bound.saturating_sub(error);
-        let right_bound = right_bound.saturating_add(error).clamp(0, sa_len - 1);
+        // Compute bounds from PWL function. The segments start at the smallest first minimizer
+        // of the reference, so a query whose first minimizer is smaller has no occurrences.
+        let (Some(left_bound), Some(right_bound)) = (
+            lookup(&self.query_mode_aux_data.plr_begin_segments, first_kmer),
+            lookup(&self.query_mode_aux_data.plr_end_segments, first_kmer),
+        ) else {
+            return Some(Candidates { suffix_array: &[], range: (0, 0), query_super_kmers });
+        };
+        // Account for error and clamp accordingly. Float to integer casts saturate, so negative
+        // predictions become 0.
+        let right_bound = (right_bound.ceil() as usize).saturating_add(error).min(sa_len - 1);
+        let left_bound = (left_bound as usize).saturating_sub(error).min(right_bound);
         let suffix_array = &self.suffix_array[left_bound..(right_bound + 1)];
 
         // println!(
@@ -415,54 +1137,22 @@ impl Queryable for SuffixArray<PWLLearnedQuery> {
         //     (right_bound - left_bound + 1) as f64 / self.suffix_array.len() as f64 * 100.0
         // );
 
-        // Look for first index in suffix array == kmer
-        let left_idx = suffix_array.partition_point(|&s| {
-            cmp_slice_to_query(&self.super_kmers[s..self.super_kmers.len()]) == Ordering::Less
-        });
-        // Look for first index in suffix array > kmer
-        let right_idx = suffix_array.partition_point(|&s| {
-            cmp_slice_to_query(&self.super_kmers[s..self.super_kmers.len()]) != Ordering::Greater
-        });
-
-        if left_idx == right_idx {
-            // Query not present
-            //return (None, false);
-            return (Vec::new(), 0);
-        }
-
-        // Query could be present anywhere in the range
-        let mut result = Vec::new();
-        let mut false_positives = 0;
-        let original_string = self.underlying_kmers.get_original_string();
-        for i in left_idx..right_idx {
-            let super_kmers = &self.super_kmers[suffix_array[i]..self.super_kmers.len()]
-                [0..query_super_kmers.len()];
-
-            let first_super_kmer = super_kmers.first().unwrap();
-            let last_super_kmer = super_kmers.last().unwrap();
-            let start_pos = first_super_kmer.start_pos;
-            let end_pos = last_super_kmer.start_pos + last_super_kmer.length;
-
-            let mut found = false;
-            for (i, w) in original_string[start_pos..end_pos]
-                .windows(query.len())
-                .enumerate()
-            {
-                if w == query {
-                    //return (Some(start_pos + i), false);
-                    found = true;
-                    result.push(start_pos + i);
-                    break;
-                }
-            }
+        let range = self.search(suffix_array, &query_super_kmers);
+        Some(Candidates { suffix_array, range, query_super_kmers })
+    }
+}
 
-            if found == false {
-                false_positives += 1;
-            }
-        }
+impl Queryable for SuffixArray<PWLLearnedQuery> {
+    fn locate(&self, query: &[u8], mode: LocateMode) -> (Vec<usize>, usize) {
+        let Some(candidates) = self.candidates(query) else { return self.locate_in_gaps(query, mode) };
+        self.verify(&candidates, query, mode)
+    }
 
-        //(None, true)
-        (result, false_positives)
+    fn count(&self, query: &[u8], verified: bool) -> usize {
+        let Some(candidates) = self.candidates(query) else {
+            return self.locate_in_gaps(query, LocateMode::All).0.len();
+        };
+        self.count_candidates(&candidates, query, verified)
     }
 }
 
@@ -470,6 +1160,9 @@ impl Queryable for SuffixArray<PWLLearnedQuery> {
 mod test {
     use super::*;
     use crate::{Alphabet, fasta::read_sequences};
+    use crate::iter_order_by::MyIterOrderBy;
+    use crate::transform::AlphabetReduction;
+    use rand::Rng;
 
     #[test]
     fn groundtruthquery_success() {
@@ -477,10 +1170,10 @@ mod test {
         let k = 3;
         let w = 3;
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         let std_suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, MinimizerOrder::Lexicographic, ());
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         let gt_suffix_array = SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, MinimizerOrder::Lexicographic, ());
 
         for query_len in 5..sequence.len() {
@@ -496,15 +1189,15 @@ mod test {
     #[test]
     fn standardquery_success() {
         let sequence = "ACTGACCCGTAGCGCTA".as_bytes();
-        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence].into_iter() {
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling].into_iter() {
             for k in 1..sequence.len() {
                 for w in 1..sequence.len() - k + 1 {
                     let alphabet = Alphabet::from_bytes(sequence);
-                    let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+                    let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
                     let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, o, ());
 
                     let alphabet = Alphabet::from_bytes(sequence);
-                    let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+                    let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
                     let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, o, ());
 
                     println!("{:#?}", suffix_array);
@@ -512,9 +1205,12 @@ mod test {
                     for query_len in (k + w - 1)..sequence.len() {
                         for window in sequence.windows(query_len) {
                             dbg!(std::str::from_utf8(&window).unwrap());
-                            match suffix_array.query(window).0 {
-                                Some(i) => assert_eq!(&sequence[i..(i + window.len())], window),
-                                None => assert!(suffix_array_gt.query(window).0.is_none()),
+                            let result = suffix_array.query(window).0;
+                            for &i in &result {
+                                assert_eq!(&sequence[i..(i + window.len())], window);
+                            }
+                            if result.is_empty() {
+                                assert!(suffix_array_gt.query(window).0.is_empty());
                             }
                         }
                     }
@@ -526,18 +1222,18 @@ mod test {
     #[test]
     fn standardquery_nomatch() {
         let sequence = "ACTGACCCGTAGCGCTA".as_bytes();
-        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence].into_iter() {
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling].into_iter() {
             let k = 3;
             let w = 3;
             let alphabet = Alphabet::from_bytes(sequence);
-            let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+            let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
             let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, o, ());
 
             for query_len in 5..sequence.len() {
                 for (i, window) in sequence.windows(query_len).enumerate() {
                     let mut window = window.to_owned();
                     window[0] = if window[0] != b'A' { b'A' } else { b'C' };
-                    assert_eq!(suffix_array.query(&window).0, None);
+                    assert!(suffix_array.query(&window).0.is_empty());
                 }
             }
 
@@ -549,7 +1245,7 @@ mod test {
                     } else {
                         b'C'
                     };
-                    assert_eq!(suffix_array.query(&window).0, None);
+                    assert!(suffix_array.query(&window).0.is_empty());
                 }
             }
 
@@ -557,7 +1253,598 @@ mod test {
                 for (i, window) in sequence.windows(query_len).enumerate() {
                     let mut window = window.to_owned();
                     window[1] = if window[1] != b'A' { b'A' } else { b'C' };
-                    assert_eq!(suffix_array.query(&window).0, None);
+                    assert!(suffix_array.query(&window).0.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn suffix_array_matches_comparison_sort() {
+        let mut rng = rand::rng();
+        let mut sequences: Vec<Vec<u8>> = vec![b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_vec(), b"ACACACACACACACACACACACACGTACACACACACACAC".to_vec()];
+        for _ in 0..20 {
+            sequences.push((0..rng.random_range(10..300)).map(|_| b"ACGT"[rng.random_range(0..4)]).collect());
+        }
+
+        for sequence in &sequences {
+            for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash] {
+                for (k, w) in [(1, 1), (2, 3), (3, 3), (4, 2)] {
+                    let kmers = KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                    let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, o, ());
+
+                    let super_kmers = &suffix_array.super_kmers;
+                    let minimizers: Vec<Kmer> = (0..super_kmers.len()).map(|i| super_kmers.minimizer(i)).collect();
+                    let mut expected: Vec<usize> = (0..super_kmers.len()).collect();
+                    expected.sort_by(|&i1, &i2| {
+                        minimizers[i1..].iter().my_cmp_by(minimizers[i2..].iter(), |x, y| {
+                            suffix_array.underlying_kmers.compare_kmers(x, y)
+                        })
+                    });
+                    assert_eq!(suffix_array.suffix_array, expected);
+                    // The sentinel suffix is the greatest
+                    assert_eq!(suffix_array.suffix_array.last(), Some(&(super_kmers.len() - 1)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn packed_super_kmers_match_computed() {
+        let sequence = "ACTGACNNNNNCGTAGCGCTAACTGGTCAGGCTAGCATNNNNNNNNACGTAACTGACCCGTAGCGCTAACTGG".as_bytes();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash] {
+            for (k, w) in [(1, 1), (2, 3), (3, 3), (4, 2)] {
+                let kmers = KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, o, ());
+                let kmers = &suffix_array.underlying_kmers;
+                let packed = &suffix_array.super_kmers;
+
+                let expected = kmers.compute_super_kmers(w, o, 0, None).unwrap();
+                assert_eq!(packed.len(), expected.len() + 1);
+                for (i, super_kmer) in expected.iter().enumerate() {
+                    assert_eq!(packed.start_pos(i), super_kmer.start_pos);
+                    assert_eq!(packed.length(i), super_kmer.length);
+                    assert_eq!(kmers.compare_kmers(&packed.minimizer(i), &super_kmer.minimizer), Ordering::Equal);
+                }
+                assert_eq!(packed.start_pos(expected.len()), sequence.len());
+                assert!(packed.minimizer(expected.len()) == Kmer::Sentinel);
+            }
+        }
+    }
+
+    #[test]
+    fn lcp_matches_naive() {
+        let mut rng = rand::rng();
+        let mut sequences: Vec<Vec<u8>> = vec![b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_vec(), b"ACACACACACACACACACACACACGTACACACACACACAC".to_vec()];
+        for _ in 0..20 {
+            sequences.push((0..rng.random_range(10..300)).map(|_| b"ACGT"[rng.random_range(0..4)]).collect());
+        }
+
+        for sequence in &sequences {
+            for (k, w) in [(1, 1), (2, 3), (3, 3), (4, 2)] {
+                let kmers = KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, MinimizerOrder::Lexicographic, ()).with_lcp();
+
+                let super_kmers = &suffix_array.super_kmers;
+                let minimizers: Vec<Kmer> = (0..super_kmers.len()).map(|i| super_kmers.minimizer(i)).collect();
+                let kmers = &suffix_array.underlying_kmers;
+                let mut expected = vec![0];
+                for pair in suffix_array.suffix_array.windows(2) {
+                    expected.push(
+                        minimizers[pair[0]..]
+                            .iter()
+                            .zip(&minimizers[pair[1]..])
+                            .take_while(|(x, y)| kmers.compare_kmers(x, y) == Ordering::Equal)
+                            .count(),
+                    );
+                }
+                assert_eq!(suffix_array.lcp(), Some(expected.as_slice()));
+            }
+        }
+    }
+
+    #[test]
+    fn lcp_search_matches_binary_search() {
+        let mut rng = rand::rng();
+        // Repetitive sequences give long LCPs
+        let mut sequences: Vec<Vec<u8>> = vec![b"ACACACACACACACACACACACACGTACACACACACACACACACACACACAC".to_vec()];
+        for _ in 0..20 {
+            let unit: Vec<u8> = (0..rng.random_range(5..30)).map(|_| b"ACGT"[rng.random_range(0..4)]).collect();
+            let mut sequence: Vec<u8> = unit.iter().cycle().take(rng.random_range(50..300)).copied().collect();
+            for _ in 0..5 {
+                let i = rng.random_range(0..sequence.len());
+                sequence[i] = b"ACGT"[rng.random_range(0..4)];
+            }
+            sequences.push(sequence);
+        }
+
+        for sequence in &sequences {
+            for (k, w) in [(1, 1), (2, 3), (3, 3), (4, 2)] {
+                let build = || KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, ());
+                let suffix_array_lcp = SuffixArray::<LcpQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, ());
+
+                for _ in 0..50 {
+                    let len = rng.random_range(k + w - 1..=40.min(sequence.len()));
+                    let query: Vec<u8> = if rng.random_bool(0.5) {
+                        let start = rng.random_range(0..=sequence.len() - len);
+                        sequence[start..start + len].to_vec()
+                    } else {
+                        (0..len).map(|_| b"ACGT"[rng.random_range(0..4)]).collect()
+                    };
+
+                    let query_super_kmers = suffix_array_std.query_super_kmers(&query).unwrap();
+                    let expected = suffix_array_std.search(&suffix_array_std.suffix_array, &query_super_kmers);
+                    let candidates = suffix_array_lcp.candidates(&query).unwrap();
+                    assert_eq!(candidates.range, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fm_index_matches_suffix_array() {
+        let bincode_config = bincode::config::standard()
+            .with_little_endian()
+            .with_fixed_int_encoding();
+
+        let mut rng = rand::rng();
+        let sequence: Vec<u8> = (0..5000).map(|_| b"ACGT"[rng.random_range(0..4)]).collect();
+        let (k, w) = (7, 5);
+        let build = || KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap();
+        let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, ());
+        let std_size = bincode::encode_to_vec(&suffix_array_std, bincode_config).unwrap().len();
+
+        let mut previous_size = usize::MAX;
+        for sample_rate in [1, 3, 16, 64] {
+            let suffix_array_fm = SuffixArray::<FmIndexQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, sample_rate);
+
+            // Every suffix array entry is recovered from the samples
+            let fm_index = &suffix_array_fm.query_mode_aux_data;
+            for (i, &suffix) in suffix_array_std.suffix_array.iter().enumerate() {
+                assert_eq!(fm_index.locate(i), suffix);
+            }
+
+            for _ in 0..200 {
+                let len = rng.random_range(k + w - 1..60);
+                let start = rng.random_range(0..=sequence.len() - len);
+                let query = &sequence[start..start + len];
+                assert_eq!(suffix_array_fm.query(query).0, suffix_array_std.query(query).0);
+            }
+
+            // One sample is kept per multiple of the sample rate among the text positions, and
+            // sparser samples take less space. Even keeping every sample, the Burrows-Wheeler
+            // transform and the samples are smaller than the plain suffix array.
+            assert_eq!(fm_index.samples.len(), suffix_array_std.suffix_array.len().div_ceil(sample_rate));
+            let fm_size = bincode::encode_to_vec(&suffix_array_fm, bincode_config).unwrap().len();
+            assert!(fm_size < std_size, "{} >= {}", fm_size, std_size);
+            assert!(fm_size < previous_size);
+            previous_size = fm_size;
+        }
+    }
+
+    #[test]
+    fn fm_index_is_smaller_than_suffix_array() {
+        let bincode_config = bincode::config::standard()
+            .with_little_endian()
+            .with_fixed_int_encoding();
+
+        // Nearly every minimizer is distinct, so the table of distinct minimizers is as long as
+        // the suffix array
+        let mut rng = rand::rng();
+        let sequence: Vec<u8> = (0..20000).map(|_| b"ACGT"[rng.random_range(0..4)]).collect();
+        let (k, w) = (21, 11);
+        let build = || KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap();
+        let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, ());
+        let std_size = bincode::encode_to_vec(&suffix_array_std, bincode_config).unwrap().len();
+        for sample_rate in [4, 16, 64] {
+            let suffix_array_fm = SuffixArray::<FmIndexQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, sample_rate);
+            let fm_size = bincode::encode_to_vec(&suffix_array_fm, bincode_config).unwrap().len();
+            assert!(fm_size < std_size, "{} >= {}", fm_size, std_size);
+        }
+    }
+
+    #[test]
+    fn hash_seed_roundtrip() {
+        let bincode_config = bincode::config::standard()
+            .with_little_endian()
+            .with_fixed_int_encoding();
+
+        let sequence = "ACTGACCCGTAGCGCTAACTGGTCAGGCTAGCATTTGACGGATCCAGTACGATTGCAGGACTTAC".as_bytes();
+        let (k, w) = (3, 4);
+        let build = |seed| {
+            let kmers = KmerSequence::from_bytes(sequence, k, Alphabet::from_bytes(sequence)).unwrap();
+            SuffixArray::<StandardQuery>::from_kmers_seeded(kmers, w, MinimizerOrder::Hash, seed, ())
+        };
+        assert_ne!(build(0).super_kmers, build(42).super_kmers);
+
+        // Queries must be transformed with the seed the index was built with
+        let encoded = bincode::encode_to_vec(build(42), bincode_config).unwrap();
+        let (suffix_array, _): (SuffixArray<StandardQuery>, _) =
+            bincode::decode_from_slice(&encoded, bincode_config).unwrap();
+        assert_eq!(suffix_array.hash_seed, 42);
+        for query_len in (k + w - 1)..sequence.len() {
+            for (i, window) in sequence.windows(query_len).enumerate() {
+                assert!(suffix_array.query(window).0.contains(&i));
+            }
+        }
+    }
+
+    #[test]
+    fn decoded_index_matches_built() {
+        let bincode_config = bincode::config::standard()
+            .with_little_endian()
+            .with_fixed_int_encoding();
+
+        let sequence = "ACTGACnnNNNcgtagcGCTAACTGGTCAGGCTAGCATNNRYNNACGTAACTGACCCGTAGCGCTAACTGGN".as_bytes();
+        for (k, w) in [(2, 3), (3, 3), (4, 2)] {
+            let kmers = KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+            let built = SuffixArray::<StandardQuery>::from_kmers(kmers, w, MinimizerOrder::Occurrence, ());
+
+            // The k-mers aren't stored, so queries must only read the packed reference
+            let encoded = bincode::encode_to_vec(&built, bincode_config).unwrap();
+            let (decoded, _): (SuffixArray<StandardQuery>, _) =
+                bincode::decode_from_slice(&encoded, bincode_config).unwrap();
+
+            for query_len in (k + w - 1)..12 {
+                for window in sequence.windows(query_len) {
+                    assert_eq!(decoded.query(window), built.query(window));
+                    assert_eq!(decoded.count(window, true), built.count(window, true));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn build_is_independent_of_thread_count() {
+        let bincode_config = bincode::config::standard()
+            .with_little_endian()
+            .with_fixed_int_encoding();
+
+        // Long enough to be split into several chunks of windows
+        let mut rng = rand::rng();
+        let sequence: Vec<u8> = (0..20_000).map(|_| b"ACGTN"[rng.random_range(0..5)]).collect();
+        let (k, w) = (9, 5);
+
+        let build_with_threads = |threads: usize, o: MinimizerOrder, canonical: bool| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let kmers = if canonical {
+                    KmerSequence::from_bytes_canonical(&sequence, k, Alphabet::dna()).unwrap()
+                } else {
+                    KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap()
+                };
+                let suffix_array = SuffixArray::<BloomFilterQuery>::from_kmers_seeded(kmers, w, o, 7, 0.01);
+                bincode::encode_to_vec(suffix_array, bincode_config).unwrap()
+            })
+        };
+
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling] {
+            for canonical in [false, true] {
+                if canonical && !o.supports_canonical() {
+                    continue;
+                }
+                assert!(build_with_threads(1, o, canonical) == build_with_threads(4, o, canonical), "{:?}", o);
+            }
+        }
+    }
+
+    #[test]
+    fn syncmers_match_ground_truth() {
+        let sequence = "ACTGACCCGTAGCGCTAACTGGTCAGGCTAGCATTTGACGGATCCAGTNNACGATTGCAGGACTTAC".as_bytes();
+        for k in [3, 4, 5] {
+            for params in [SyncmerParams::closed(k, 2), SyncmerParams::open(2, 1), SyncmerParams::open(1, k - 1)] {
+                let build = || KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(build(), 1, MinimizerOrder::Lexicographic, ());
+                let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers_syncmers(build(), params.clone(), ());
+                let suffix_array_bf = SuffixArray::<BloomFilterQuery>::from_kmers_syncmers(build(), params.clone(), 0.01);
+                let suffix_array_canonical = SuffixArray::<StandardQuery>::from_kmers_syncmers(
+                    KmerSequence::from_bytes_canonical(sequence, k, Alphabet::dna()).unwrap(), params.clone(), ());
+
+                for query_len in k..16 {
+                    for window in sequence.windows(query_len) {
+                        // Includes queries that lie entirely in a gap between syncmers
+                        let expected = suffix_array_gt.query(window).0;
+                        assert_eq!(suffix_array_std.query(window).0, expected);
+                        assert_eq!(suffix_array_std.count(window, true), expected.len());
+                        assert_eq!(suffix_array_bf.query(window).0, expected);
+                        assert_eq!(suffix_array_canonical.query(window).0, expected);
+                        assert_eq!(suffix_array_std.locate(window, LocateMode::First).0.len(), 1);
+
+                        // Mostly absent from the reference, so the bloom filters reject them
+                        let mut mutated = window.to_owned();
+                        mutated[query_len / 2] = if mutated[query_len / 2] == b'A' { b'C' } else { b'A' };
+                        assert_eq!(suffix_array_bf.query(&mutated).0, suffix_array_gt.query(&mutated).0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bloomfilterquery_roundtrip() {
+        let bincode_config = bincode::config::standard()
+            .with_little_endian()
+            .with_fixed_int_encoding();
+
+        let sequence = "ACTGACCCGTAGCGCTAACTGGTCAGGCTAGCAT".as_bytes();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling].into_iter() {
+            for (k, w) in [(1, 1), (2, 3), (3, 3), (4, 2)] {
+                let alphabet = Alphabet::from_bytes(sequence);
+                let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
+                let suffix_array = SuffixArray::<BloomFilterQuery>::from_kmers(kmers, w, o, 0.01);
+
+                // The bloom filters must survive serialization
+                let encoded = bincode::encode_to_vec(suffix_array, bincode_config).unwrap();
+                let (suffix_array, _): (SuffixArray<BloomFilterQuery>, _) =
+                    bincode::decode_from_slice(&encoded, bincode_config).unwrap();
+
+                let alphabet = Alphabet::from_bytes(sequence);
+                let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, o, ());
+
+                for query_len in (k + w - 1)..sequence.len() {
+                    for window in sequence.windows(query_len) {
+                        let result = suffix_array.query(window).0;
+                        assert!(!result.is_empty());
+                        for &i in &result {
+                            assert_eq!(&sequence[i..(i + window.len())], window);
+                        }
+
+                        let mut window = window.to_owned();
+                        let last = window.len() - 1;
+                        window[last] = if window[last] != b'A' { b'A' } else { b'C' };
+                        assert_eq!(
+                            suffix_array.query(&window).0.is_empty(),
+                            suffix_array_gt.query(&window).0.is_empty()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn locate_all_matches_ground_truth() {
+        // Tandem repeats put several occurrences inside a single super-k-mer range
+        let sequence = "ACACACACACGTTAGTTAGTTAGTTAGCCCCCCCCATGATGATGACACACA".as_bytes();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling].into_iter() {
+            for (k, w) in [(1, 1), (2, 2), (3, 3), (2, 5)] {
+                let alphabet = Alphabet::from_bytes(sequence);
+                let build = || KmerSequence::from_bytes(sequence, k, alphabet.clone()).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(build(), w, o, ());
+                let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, o, ());
+                let suffix_array_bf = SuffixArray::<BloomFilterQuery>::from_kmers(build(), w, o, 0.01);
+                let suffix_array_pwl = SuffixArray::<PWLLearnedQuery>::from_kmers(build(), w, o, 10.0);
+                let suffix_array_lcp = SuffixArray::<LcpQuery>::from_kmers(build(), w, o, ());
+                let suffix_array_fm = SuffixArray::<FmIndexQuery>::from_kmers(build(), w, o, 4);
+
+                for query_len in (k + w - 1)..12 {
+                    for window in sequence.windows(query_len) {
+                        let expected = suffix_array_gt.query(window).0;
+                        assert_eq!(suffix_array_std.query(window).0, expected);
+                        assert_eq!(suffix_array_bf.query(window).0, expected);
+                        assert_eq!(suffix_array_pwl.query(window).0, expected);
+                        assert_eq!(suffix_array_lcp.query(window).0, expected);
+                        assert_eq!(suffix_array_fm.query(window).0, expected);
+
+                        let first = suffix_array_std.locate(window, LocateMode::First).0;
+                        assert_eq!(first.len(), 1);
+                        assert!(expected.contains(&first[0]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn count_matches_ground_truth() {
+        let sequence = "ACACACACACGTTAGTTAGTTAGTTAGCCCCCCCCATGATGATGACACACA".as_bytes();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling].into_iter() {
+            for (k, w) in [(1, 1), (2, 2), (3, 3), (2, 5)] {
+                let alphabet = Alphabet::from_bytes(sequence);
+                let build = || KmerSequence::from_bytes(sequence, k, alphabet.clone()).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(build(), w, o, ());
+                let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, o, ());
+                let suffix_array_pwl = SuffixArray::<PWLLearnedQuery>::from_kmers(build(), w, o, 10.0);
+                let suffix_array_lcp = SuffixArray::<LcpQuery>::from_kmers(build(), w, o, ());
+                let suffix_array_fm = SuffixArray::<FmIndexQuery>::from_kmers(build(), w, o, 4);
+
+                for query_len in (k + w - 1)..12 {
+                    for window in sequence.windows(query_len) {
+                        let expected = suffix_array_gt.query(window).0.len();
+                        assert_eq!(suffix_array_gt.count(window, true), expected);
+                        assert_eq!(suffix_array_std.count(window, true), expected);
+                        assert_eq!(suffix_array_pwl.count(window, true), expected);
+                        assert_eq!(suffix_array_lcp.count(window, true), expected);
+                        assert_eq!(suffix_array_fm.count(window, true), expected);
+                        assert_eq!(suffix_array_fm.count(window, false), suffix_array_std.count(window, false));
+                        assert!(suffix_array_std.count(window, false) > 0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_segment_matches_linear_scan() {
+        let mut rng = rand::rng();
+        let sequence: Vec<u8> = (0..5000).map(|_| b"ACGT"[rng.random_range(0..4)]).collect();
+        let kmers = KmerSequence::from_bytes(&sequence, 6, Alphabet::dna()).unwrap();
+        let suffix_array = SuffixArray::<PWLLearnedQuery>::from_kmers(kmers, 4, MinimizerOrder::Lexicographic, 1.0);
+
+        let aux_data = &suffix_array.query_mode_aux_data;
+        for segments in [&aux_data.plr_begin_segments, &aux_data.plr_end_segments] {
+            assert!(segments.len() > 10);
+            // Probe both sides of every boundary, and keys outside every segment
+            let probes = segments
+                .iter()
+                .flat_map(|segment| [segment.start, segment.stop])
+                .flat_map(|x| [x.next_down(), x, x.next_up(), x + 0.5])
+                .chain([-1.0, f64::MAX]);
+            for x in probes {
+                let expected = segments.iter().find(|&segment| segment.start <= x && x < segment.stop);
+                assert_eq!(find_segment(segments, x).map(|segment| segment.start), expected.map(|segment| segment.start));
+            }
+        }
+    }
+
+    #[test]
+    fn pwl_random_queries_match_ground_truth() {
+        let mut rng = rand::rng();
+        // Without As in the reference, queries with As can have first minimizers smaller than
+        // every segment's start
+        let sequence: Vec<u8> = (0..3000).map(|_| b"CGT"[rng.random_range(0..3)]).collect();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Hash] {
+            for (k, w) in [(3, 2), (5, 4), (8, 3)] {
+                let build = || KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(build(), w, o, ());
+                for gamma in [0.5, 4.0, 100.0] {
+                    let suffix_array_pwl = SuffixArray::<PWLLearnedQuery>::from_kmers(build(), w, o, gamma);
+                    for _ in 0..2000 {
+                        let len = rng.random_range(k + w - 1..40);
+                        let query: Vec<u8> = if rng.random_bool(0.5) {
+                            // A substring of the reference with a few substitutions
+                            let start = rng.random_range(0..=sequence.len() - len);
+                            let mut query = sequence[start..start + len].to_vec();
+                            for _ in 0..rng.random_range(0..3) {
+                                query[rng.random_range(0..len)] = b"ACGT"[rng.random_range(0..4)];
+                            }
+                            query
+                        } else {
+                            (0..len).map(|_| b"ACGT"[rng.random_range(0..4)]).collect()
+                        };
+
+                        let expected = suffix_array_gt.query(&query).0;
+                        assert_eq!(suffix_array_pwl.query(&query).0, expected, "{:?}", std::str::from_utf8(&query));
+                        assert_eq!(suffix_array_pwl.count(&query, true), expected.len());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_both_strands() {
+        let sequence = "ACTGACCCGTAGCGCTAACTGGTCAGGCTAGCATTTAGC".as_bytes();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::Decycling].into_iter() {
+            for (k, w) in [(1, 1), (3, 3), (4, 2)] {
+                let alphabet = Alphabet::from_bytes(sequence).complement_closed();
+                let kmers = KmerSequence::from_bytes_canonical(sequence, k, alphabet.clone()).unwrap();
+                let suffix_array = SuffixArray::<StandardQuery>::from_kmers(kmers, w, o, ());
+                let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, o, ());
+
+                for query_len in (k + w - 1)..12 {
+                    for window in sequence.windows(query_len) {
+                        let window_rc = reverse_complement(window);
+                        for query in [window, window_rc.as_slice()] {
+                            let (result, _) = suffix_array.locate_stranded(query, LocateMode::All);
+                            let forward: Vec<_> = result
+                                .iter()
+                                .filter(|(_, strand)| *strand == Strand::Forward)
+                                .map(|&(i, _)| i)
+                                .collect();
+                            let reverse: Vec<_> = result
+                                .iter()
+                                .filter(|(_, strand)| *strand == Strand::Reverse)
+                                .map(|&(i, _)| i)
+                                .collect();
+                            assert_eq!(forward, suffix_array_gt.query(query).0);
+                            assert_eq!(reverse, suffix_array_gt.query(&reverse_complement(query)).0);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_characters_never_match() {
+        let sequence = "ACTGACCCGTAGCGCTAACTGGTCAGGCTAGCAT".as_bytes();
+        let (k, w) = (3, 3);
+        let alphabet = Alphabet::from_bytes(sequence);
+        let build = || KmerSequence::from_bytes(sequence, k, alphabet.clone()).unwrap();
+        let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, ());
+        let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, MinimizerOrder::Occurrence, ());
+        let suffix_array_bf = SuffixArray::<BloomFilterQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, 0.01);
+        let suffix_array_pwl = SuffixArray::<PWLLearnedQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, 10.0);
+
+        for query in ["ACTGNCCCG", "actgacccg", "CTAGCATR"] {
+            let query = query.as_bytes();
+            assert!(suffix_array_std.validate_query(query).is_err());
+            assert!(suffix_array_gt.query(query).0.is_empty());
+            assert!(suffix_array_std.query(query).0.is_empty());
+            assert!(suffix_array_bf.query(query).0.is_empty());
+            assert!(suffix_array_pwl.query(query).0.is_empty());
+            assert_eq!(suffix_array_std.count(query, true), 0);
+        }
+
+        assert!(suffix_array_std.validate_query("ACTGA".as_bytes()).is_ok());
+        assert!(suffix_array_std.validate_query("ACTG".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn dna_alphabet_matches_ground_truth() {
+        let sequence = "ACTGACNNNNNCGTAGcgctaaCTGGTCRGGCTAGCATNNNNNNNNACGTA".as_bytes();
+        for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::Decycling].into_iter() {
+            for (k, w) in [(1, 1), (2, 3), (3, 3), (4, 2)] {
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(
+                    KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap(), w, o, ());
+                let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(
+                    KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap(), w, o, ());
+                let suffix_array_canonical = SuffixArray::<StandardQuery>::from_kmers(
+                    KmerSequence::from_bytes_canonical(sequence, k, Alphabet::dna()).unwrap(), w, o, ());
+
+                for query_len in (k + w - 1)..14 {
+                    for window in sequence.windows(query_len) {
+                        for query in [window.to_ascii_uppercase(), window.to_ascii_lowercase()] {
+                            let expected = suffix_array_gt.query(&query).0;
+                            assert!(!expected.is_empty());
+                            if query.windows(k).any(|kmer| kmer.iter().all(|b| b"ACGTacgt".contains(b))) {
+                                assert_eq!(suffix_array_std.query(&query).0, expected);
+                                assert_eq!(suffix_array_std.count(&query, true), expected.len());
+                                assert_eq!(suffix_array_canonical.query(&query).0, expected);
+                            } else {
+                                // Queries made up entirely of masked windows are never found
+                                for i in suffix_array_std.query(&query).0 {
+                                    assert!(expected.contains(&i));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn protein_alphabets_match_ground_truth() {
+        let sequence = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKXAVQVKVKALPDAQ".as_bytes();
+        for reduction in [None, Some(AlphabetReduction::Murphy10), Some(AlphabetReduction::Dayhoff6)] {
+            for (k, w) in [(1, 1), (2, 3), (3, 3), (5, 2)] {
+                let build = || KmerSequence::from_bytes(sequence, k, Alphabet::protein(reduction)).unwrap();
+                let suffix_array_gt = SuffixArray::<GroundTruthQuery>::from_kmers(build(), w, MinimizerOrder::Lexicographic, ());
+                let suffix_array_std = SuffixArray::<StandardQuery>::from_kmers(build(), w, MinimizerOrder::Occurrence, ());
+
+                for query_len in (k + w - 1)..12 {
+                    for window in sequence.windows(query_len) {
+                        // A conservative substitution has the same k-mers in a reduced alphabet,
+                        // but must still not match
+                        let mut substituted = window.to_owned();
+                        substituted[0] = match substituted[0] { b'L' => b'I', b'I' => b'L', b => b };
+
+                        for query in [window, &substituted] {
+                            let actual = suffix_array_std.query(query).0;
+                            if query.contains(&b'X') && suffix_array_std.query_super_kmers(query).is_none() {
+                                // X is masked, and every window of the query overlaps it, so
+                                // there's nothing to search for
+                                assert!(actual.is_empty());
+                            } else {
+                                assert_eq!(actual, suffix_array_gt.query(query).0);
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -578,22 +1865,22 @@ mod test {
         let k = 5;
         let w = 3;
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         let suffix_array_standard = SuffixArray::<PWLLearnedQuery>::from_kmers(kmers, w, MinimizerOrder::Lexicographic, 1000.0);
 
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         let suffix_array_ground_truth = SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, MinimizerOrder::Lexicographic, ());
 
         for query in queries {
             let result = suffix_array_standard.query(query).0;
-            match result {
-                Some(i) => {
-                    // ensure that the string is actually present
-                    let slice = &sequence[i..(i + query.len())];
-                    assert_eq!(slice, query);
-                }
-                None => assert!(suffix_array_ground_truth.query(query).0.is_none()),
+            for &i in &result {
+                // ensure that the string is actually present
+                let slice = &sequence[i..(i + query.len())];
+                assert_eq!(slice, query);
+            }
+            if result.is_empty() {
+                assert!(suffix_array_ground_truth.query(query).0.is_empty());
             }
 
             // The below doesn't work because StandardQuery and GroundTruthQuery might return
@@ -617,22 +1904,22 @@ mod test {
         let k = 3;
         let w = 3;
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         let suffix_array_standard = SuffixArray::<StandardQuery>::from_kmers(kmers, w, MinimizerOrder::Occurrence, ());
 
         let alphabet = Alphabet::from_bytes(sequence);
-        let kmers = KmerSequence::from_bytes(sequence, k, alphabet);
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
         let suffix_array_ground_truth = SuffixArray::<GroundTruthQuery>::from_kmers(kmers, w, MinimizerOrder::Occurrence, ());
 
         for query in queries {
             let result = suffix_array_standard.query(query).0;
-            match result {
-                Some(i) => {
-                    // ensure that the string is actually present
-                    let slice = &sequence[i..(i + query.len())];
-                    assert_eq!(slice, query);
-                }
-                None => assert!(suffix_array_ground_truth.query(query).0.is_none()),
+            for &i in &result {
+                // ensure that the string is actually present
+                let slice = &sequence[i..(i + query.len())];
+                assert_eq!(slice, query);
+            }
+            if result.is_empty() {
+                assert!(suffix_array_ground_truth.query(query).0.is_empty());
             }
         }
     }
@@ -1,23 +1,94 @@
-use std::{cmp::Ordering, collections::{HashSet, HashMap}, fmt};
+use std::{cmp::Ordering, collections::{BTreeMap, HashSet, HashMap, VecDeque}, fmt, hash::{BuildHasherDefault, DefaultHasher}};
 
-use bimap::BiMap;
-use bincode::{Decode, Encode};
-use itertools::Itertools;
+use bimap::BiBTreeMap;
+use bincode::{de::Decoder, enc::Encoder, error::{DecodeError, EncodeError}, Decode, Encode};
+use itertools::{Either, Itertools};
+use rayon::prelude::*;
 
 use crate::int_vec::IntVec;
+use crate::packed::PackedSequence;
 
 /// A mapping from u8s in the original string to
 /// u8s that have been compressed into a smaller domain
 #[derive(Clone, Encode, Decode)]
-pub struct Alphabet(#[bincode(with_serde)] BiMap<u8, u8>);
+pub struct Alphabet {
+    // Ordered, so that alphabets serialize the same way on every build
+    #[bincode(with_serde)]
+    map: BiBTreeMap<u8, u8>,
 
-/// Represents a single k-mer.
+    /// Bytes that are encoded as another byte of `map`, e.g. every amino acid of a
+    /// reduced alphabet group is encoded as the group's representative
+    aliases: BTreeMap<u8, u8>,
+
+    /// Whether bytes are upper-cased before being mapped (e.g. soft-masked DNA)
+    fold_case: bool,
+
+    /// Whether bytes outside the alphabet are masked rather than rejected
+    mask_unknown: bool,
+}
+
+/// Represents a single k-mer. Every k-mer of a sequence uses the same variant.
 #[derive(Clone, Eq, PartialEq, Encode, Decode, Hash)]
 pub enum Kmer {
-    Data(IntVec),
+    /// A k-mer of at most `MAX_PACKED_KMER_BITS` bits, packed `Alphabet::bits()` to a
+    /// character with the first character most significant, so that integer order is
+    /// lexicographic order
+    Data(u128),
+    /// A k-mer too long to pack
+    Long(Box<IntVec>),
     Sentinel,
 }
 
+/// The largest k-mers, in bits, that are packed into a `Kmer::Data`
+const MAX_PACKED_KMER_BITS: usize = 128;
+
+/// Streams the packed k-mers (see `Kmer::Data`) of an encoded sequence, shifting in one
+/// character per k-mer. Given the encoded complement, yields the lesser of each k-mer and its
+/// reverse complement instead.
+struct RollingKmers<'a> {
+    encoded: &'a [u8],
+    encoded_complement: Option<&'a [u8]>,
+    k: usize,
+    bits: usize,
+    forward: u128,
+    reverse: u128,
+    /// The number of characters shifted in so far
+    next: usize,
+}
+
+impl<'a> RollingKmers<'a> {
+    fn new(encoded: &'a [u8], encoded_complement: Option<&'a [u8]>, k: usize, bits: usize) -> Self {
+        assert!(k * bits <= MAX_PACKED_KMER_BITS);
+        Self { encoded, encoded_complement, k, bits, forward: 0, reverse: 0, next: 0 }
+    }
+}
+
+impl Iterator for RollingKmers<'_> {
+    type Item = Kmer;
+
+    fn next(&mut self) -> Option<Kmer> {
+        let width = self.k * self.bits;
+        let mask = if width == MAX_PACKED_KMER_BITS { u128::MAX } else { (1 << width) - 1 };
+        loop {
+            let c = *self.encoded.get(self.next)? as u128;
+            self.forward = (self.forward << self.bits | c) & mask;
+            if let Some(encoded_complement) = self.encoded_complement {
+                // The complement of the newest character comes first in the reverse complement
+                let c = encoded_complement[self.next] as u128;
+                self.reverse = self.reverse >> self.bits | c << (width - self.bits);
+            }
+            self.next += 1;
+
+            if self.next >= self.k {
+                return Some(Kmer::Data(match self.encoded_complement {
+                    Some(_) => self.forward.min(self.reverse),
+                    None => self.forward,
+                }));
+            }
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Encode, Decode)]
 pub struct SuperKmer {
     // The starting position of the super-kmer in the underlying string
@@ -31,36 +102,248 @@ pub struct SuperKmer {
     pub minimizer: Kmer,
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug)]
 pub struct KmerSequence {
     alphabet: Alphabet,
 
+    /// Empty when decoded from an index
     kmers: Vec<Kmer>,
-    occ: Option<HashMap<Kmer, usize>>,
+    occ: Option<OccurrenceCounts>,
 
-    // TODO: can we efficiently compute this from kmers?
-    original_string: Vec<u8>,
+    /// The original string. Its masked intervals hold bytes outside the alphabet, and
+    /// k-mers overlapping them are never chosen as minimizers.
+    reference: PackedSequence,
 
     k: usize,
+
+    /// Whether each k-mer was replaced by the lesser of itself and its reverse complement
+    canonical: bool,
+}
+
+// The k-mers take several times more space than the rest of the sequence, and are only
+// needed while building an index, so they're left out.
+impl Encode for KmerSequence {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        Encode::encode(&self.alphabet, encoder)?;
+        Encode::encode(&self.occ, encoder)?;
+        Encode::encode(&self.reference, encoder)?;
+        Encode::encode(&self.k, encoder)?;
+        Encode::encode(&self.canonical, encoder)
+    }
+}
+
+impl<Context> Decode<Context> for KmerSequence {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            alphabet: Decode::decode(decoder)?,
+            kmers: Vec::new(),
+            occ: Decode::decode(decoder)?,
+            reference: Decode::decode(decoder)?,
+            k: Decode::decode(decoder)?,
+            canonical: Decode::decode(decoder)?,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(KmerSequence);
+
+/// Occurrence counts of k-mers. The hasher has fixed keys so that the iteration order, and with
+/// it the serialized index, is the same on every build.
+pub type OccurrenceCounts = HashMap<Kmer, usize, BuildHasherDefault<DefaultHasher>>;
+
+/// The 20 standard amino acids
+const AMINO_ACIDS: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
+
+/// Reduced amino acid alphabets, which group similar residues into a single symbol so that
+/// conservative substitutions don't change k-mers.
+#[derive(Debug, PartialEq, Copy, Clone, clap::ValueEnum)]
+pub enum AlphabetReduction {
+    /// Murphy et al. (2000), 10 groups
+    Murphy10,
+    /// Dayhoff, 6 groups
+    Dayhoff6,
+}
+
+impl AlphabetReduction {
+    fn groups(&self) -> &'static [&'static [u8]] {
+        match self {
+            AlphabetReduction::Murphy10 => &[
+                b"LVIM", b"C", b"A", b"G", b"ST", b"P", b"FYW", b"EDNQ", b"KR", b"H",
+            ],
+            AlphabetReduction::Dayhoff6 => &[b"AGPST", b"DENQ", b"HKR", b"ILMV", b"FWY", b"C"],
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone, clap::ValueEnum, Encode, Decode)]
 pub enum MinimizerOrder {
     Lexicographic,
     Occurrence,
+    /// Ranks k-mers by a seeded hash of their integer value, avoiding the bias of
+    /// lexicographic order towards low-complexity k-mers such as poly-A
+    Hash,
+    /// The mod-minimizer of Groot Koerkamp and Pibiri: the smallest t-mer of each window (by
+    /// seeded hash) at position x selects the k-mer at x mod w. Its density approaches the
+    /// lower bound of 1 / w for large k.
+    ModMinimizer,
+    /// The double decycling order of Pellow et al.: k-mers in Mykkeltveit's minimum decycling
+    /// set come first, then those in its mirror image, each group ranked by seeded hash.
+    /// Lowers density compared to `Hash` unless k is much larger than w.
+    Decycling,
+}
+
+impl MinimizerOrder {
+    /// Whether the order ranks k-mers by `kmer_to_integer`, which requires k-mers to fit in a u128
+    pub fn uses_hash(&self) -> bool {
+        matches!(self, Self::Hash | Self::ModMinimizer | Self::Decycling)
+    }
+
+    /// Whether the minimizers of canonical k-mers are the same on both strands
+    pub fn supports_canonical(&self) -> bool {
+        *self != Self::ModMinimizer
+    }
+}
+
+/// The minimum t-mer length r of the mod-minimizer; t = r + ((k - r) mod w)
+const MOD_MINIMIZER_R: usize = 4;
+
+/// Selects the k-mers whose smallest s-mer starts at one of `offsets`. Unlike minimizers,
+/// whether a k-mer is a syncmer doesn't depend on its neighbours.
+#[derive(Debug, PartialEq, Clone, Encode, Decode)]
+pub struct SyncmerParams {
+    pub s: usize,
+    pub offsets: Vec<usize>,
+}
+
+impl SyncmerParams {
+    /// Closed syncmers, whose smallest s-mer is at either end. Every k - s consecutive
+    /// k-mers contain at least one of them.
+    pub fn closed(k: usize, s: usize) -> Self {
+        assert!(1 <= s && s <= k);
+        let mut offsets = vec![0, k - s];
+        offsets.dedup();
+        Self { s, offsets }
+    }
+
+    /// Open syncmers, whose smallest s-mer is at `offset`
+    #[cfg(test)]
+    pub fn open(s: usize, offset: usize) -> Self {
+        Self { s, offsets: vec![offset] }
+    }
+}
+
+/// A seeded 64-bit hash of a u128.
+fn hash_integer(x: u128, seed: u64) -> u64 {
+    mix64(mix64(seed ^ x as u64) ^ (x >> 64) as u64)
+}
+
+/// Inputs shorter than this are processed on the calling thread, e.g. when transforming queries.
+/// Tests use small thresholds so that short sequences exercise the parallel paths.
+const PARALLEL_MIN_LEN: usize = if cfg!(test) { 1 << 8 } else { 1 << 14 };
+
+/// The number of windows each task of `chunked_sliding_window_argmin` handles. Fixed rather than
+/// derived from the number of threads so that the output doesn't depend on it.
+const WINDOW_CHUNK_LEN: usize = if cfg!(test) { 1 << 10 } else { 1 << 16 };
+
+/// Computes `f(0), ..., f(n - 1)`, in parallel if `n` is large enough to be worth it.
+fn par_map<T: Send>(n: usize, f: impl Fn(usize) -> T + Sync + Send) -> Vec<T> {
+    if n < PARALLEL_MIN_LEN {
+        (0..n).map(f).collect()
+    } else {
+        (0..n).into_par_iter().with_min_len(PARALLEL_MIN_LEN).map(f).collect()
+    }
+}
+
+/// Returns the index of the smallest unmasked item of each window of `w` items, or None if
+/// every item in the window is masked. Ties are broken towards the leftmost item.
+///
+/// Uses a monotone deque of indices, so each item is compared O(1) times amortized.
+fn sliding_window_argmin(
+    masked: &[bool],
+    w: usize,
+    cmp: impl Fn(usize, usize) -> Ordering,
+) -> Vec<Option<usize>> {
+    assert!(masked.len() >= w);
+    assert!(w >= 1);
+
+    // Indices of candidate minima, increasing in position and non-decreasing in order
+    let mut deque: VecDeque<usize> = VecDeque::with_capacity(w);
+    let mut minima = Vec::with_capacity(masked.len() - w + 1);
+    for (i, &masked) in masked.iter().enumerate() {
+        if !masked {
+            // Earlier items greater than this one can never be a minimum again
+            while deque.back().is_some_and(|&j| cmp(j, i) == Ordering::Greater) {
+                deque.pop_back();
+            }
+            deque.push_back(i);
+        }
+
+        if i + 1 >= w {
+            // Drop items that have slid out of the window [i + 1 - w, i]
+            while deque.front().is_some_and(|&j| j + w <= i) {
+                deque.pop_front();
+            }
+            minima.push(deque.front().copied());
+        }
+    }
+    minima
+}
+
+/// `sliding_window_argmin` over chunks of `WINDOW_CHUNK_LEN` windows in parallel, each chunk
+/// also scanning the w - 1 items that overlap the next chunk. Gives the same result as a
+/// single scan.
+fn chunked_sliding_window_argmin(
+    masked: &[bool],
+    w: usize,
+    cmp: impl Fn(usize, usize) -> Ordering + Sync,
+) -> Vec<Option<usize>> {
+    assert!(masked.len() >= w);
+    let num_windows = masked.len() + 1 - w;
+    if num_windows <= WINDOW_CHUNK_LEN {
+        return sliding_window_argmin(masked, w, cmp);
+    }
+
+    let chunk_starts: Vec<usize> = (0..num_windows).step_by(WINDOW_CHUNK_LEN).collect();
+    chunk_starts
+        .into_par_iter()
+        .flat_map_iter(|start| {
+            let end = (start + WINDOW_CHUNK_LEN).min(num_windows);
+            sliding_window_argmin(&masked[start..end + w - 1], w, |i, j| cmp(start + i, start + j))
+                .into_iter()
+                .map(move |i| i.map(|i| start + i))
+        })
+        .collect()
+}
+
+/// Mixes the bits of a 64-bit integer (the splitmix64 finalizer).
+fn mix64(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58476d1ce4e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
 }
 
 impl Alphabet {
     pub fn from_bytes(sequence: &[u8]) -> Self {
+        Self::from_sequences([sequence])
+    }
+
+    /// Builds a single alphabet covering the bytes of every sequence, so that k-mers from
+    /// different records of the same reference are encoded identically.
+    pub fn from_sequences<'a, I: IntoIterator<Item = &'a [u8]>>(sequences: I) -> Self {
         // Construct a mapping from u8 -> compressed u8 of the
         // bytes in the original sequence
-        let mut alphabet = BiMap::new();
+        let mut alphabet = BiBTreeMap::new();
 
         // BTreeSet is helpful to keep ordering the same in original
         // and transformed alphabets
         //let bytes_seen: BTreeSet<u8> = BTreeSet::from_iter(sequence.iter().cloned());
 
-        let bytes_seen: HashSet<u8> = HashSet::from_iter(sequence.iter().cloned());
+        let bytes_seen: HashSet<u8> = sequences
+            .into_iter()
+            .flat_map(|sequence| sequence.iter().cloned())
+            .collect();
         let mut bytes_seen: Vec<_> = Vec::from_iter(bytes_seen.iter().cloned());
         // TODO: This sort should be based on the ordering!
         bytes_seen.sort();
@@ -72,51 +355,273 @@ impl Alphabet {
             alphabet.insert(b, i);
         }
 
-        Self(alphabet)
+        Self {
+            map: alphabet,
+            aliases: BTreeMap::new(),
+            fold_case: false,
+            mask_unknown: false,
+        }
+    }
+
+    /// The fixed 2-bit DNA alphabet. Lowercase (soft-masked) bases are treated as their
+    /// uppercase equivalents, and N runs and other non-ACGT bytes are masked.
+    pub fn dna() -> Self {
+        let map = BiBTreeMap::from_iter([(b'A', 0), (b'C', 1), (b'G', 2), (b'T', 3)]);
+
+        Self {
+            map,
+            aliases: BTreeMap::new(),
+            fold_case: true,
+            mask_unknown: true,
+        }
+    }
+
+    /// The amino acid alphabet, optionally reduced to groups of similar residues.
+    /// Case is ignored, and non-standard residues (X, B, Z, U, O, stop codons...) are masked.
+    pub fn protein(reduction: Option<AlphabetReduction>) -> Self {
+        let groups: Vec<&[u8]> = match reduction {
+            Some(reduction) => reduction.groups().to_vec(),
+            None => AMINO_ACIDS.chunks(1).collect(),
+        };
+
+        // Each group is encoded as its smallest residue
+        let mut representatives: Vec<u8> = groups.iter().map(|g| *g.iter().min().unwrap()).collect();
+        representatives.sort();
+        let map = BiBTreeMap::from_iter(representatives.into_iter().zip(0..));
+
+        let aliases = groups
+            .iter()
+            .flat_map(|g| {
+                let representative = *g.iter().min().unwrap();
+                g.iter().map(move |&b| (b, representative))
+            })
+            .filter(|(b, representative)| b != representative)
+            .collect();
+
+        Self {
+            map,
+            aliases,
+            fold_case: true,
+            mask_unknown: true,
+        }
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.map.len()
+    }
+
+    /// Returns the compressed value of `b`, or None if `b` isn't in the alphabet.
+    pub fn encode(&self, b: u8) -> Option<u8> {
+        let b = if self.fold_case { b.to_ascii_uppercase() } else { b };
+        let b = self.aliases.get(&b).copied().unwrap_or(b);
+        self.map.get_by_left(&b).copied()
+    }
+
+    /// The bytes that queries are matched against: every byte of the alphabet, including
+    /// aliases, upper-cased if case is ignored. Sorted.
+    pub fn residues(&self) -> Vec<u8> {
+        self.map.left_values().chain(self.aliases.keys()).copied().sorted().dedup().collect()
+    }
+
+    /// Returns `b` as it appears in `residues`, or None if `b` isn't in the alphabet.
+    pub fn residue(&self, b: u8) -> Option<u8> {
+        let b = if self.fold_case { b.to_ascii_uppercase() } else { b };
+        self.encode(b).map(|_| b)
+    }
+
+    /// Whether alphabet size ^ k fits in a u128, so that k-mers can be mapped to integers.
+    pub fn kmers_fit_in_u128(&self, k: usize) -> bool {
+        u32::try_from(k)
+            .ok()
+            .and_then(|k| (self.len().max(2) as u128).checked_pow(k))
+            .is_some()
+    }
+
+    /// The number of bits needed to store a single encoded character.
+    pub fn bits(&self) -> usize {
+        // Even a single-character alphabet needs one bit per character
+        let len = self.len().max(2);
+        if len.is_power_of_two() {
+            len.ilog2() as usize
+        } else {
+            len.ilog2() as usize + 1
+        }
+    }
+
+    /// Whether bytes outside the alphabet are masked rather than rejected.
+    pub fn masks_unknown(&self) -> bool {
+        self.mask_unknown
     }
+
+    /// Compares two strings as this alphabet sees them.
+    pub fn bytes_eq(&self, left: &[u8], right: &[u8]) -> bool {
+        if self.fold_case {
+            left.eq_ignore_ascii_case(right)
+        } else {
+            left == right
+        }
+    }
+
+    /// Extends the alphabet with the complement of every base it contains,
+    /// so that reverse complements can always be encoded.
+    pub fn complement_closed(&self) -> Self {
+        let bytes: Vec<u8> = self
+            .map
+            .left_values()
+            .flat_map(|&b| [b, complement(b)])
+            .collect();
+        Self {
+            aliases: self.aliases.clone(),
+            fold_case: self.fold_case,
+            mask_unknown: self.mask_unknown,
+            ..Self::from_bytes(&bytes)
+        }
+    }
+}
+
+/// Returns the complement of a DNA base, preserving case.
+/// Bytes other than A, C, G and T are their own complement.
+pub fn complement(b: u8) -> u8 {
+    match b {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => b,
+    }
+}
+
+pub fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence.iter().rev().map(|&b| complement(b)).collect()
 }
 
 impl KmerSequence {
-    pub fn from_bytes(sequence: &[u8], k: usize, alphabet: Alphabet) -> Self {
+    /// Returns None if `sequence` contains a byte that isn't in `alphabet`,
+    /// unless the alphabet masks such bytes.
+    pub fn from_bytes(sequence: &[u8], k: usize, alphabet: Alphabet) -> Option<Self> {
+        Self::from_bytes_with(sequence, k, alphabet, false)
+    }
+
+    /// Like `from_bytes`, but every k-mer is replaced by its canonical form (the lesser of
+    /// the k-mer and its reverse complement), so that both strands share minimizers.
+    /// Also returns None if the complement of a byte in `sequence` isn't in `alphabet`.
+    pub fn from_bytes_canonical(sequence: &[u8], k: usize, alphabet: Alphabet) -> Option<Self> {
+        Self::from_bytes_with(sequence, k, alphabet, true)
+    }
+
+    fn from_bytes_with(sequence: &[u8], k: usize, alphabet: Alphabet, canonical: bool) -> Option<Self> {
         assert!(k > 0 && k <= sequence.len());
 
         // Compute the number of bits we need to store a single underlying character
-        let bits_underlying = if alphabet.len().is_power_of_two() {
-            alphabet.len().ilog2()
+        let bits = alphabet.bits();
+
+        // Record runs of bytes outside the alphabet, if the alphabet allows them
+        let mut masked: Vec<(usize, usize)> = Vec::new();
+        for (i, &b) in sequence.iter().enumerate() {
+            if alphabet.encode(b).is_some() {
+                continue;
+            }
+            if !alphabet.masks_unknown() {
+                return None;
+            }
+            match masked.last_mut() {
+                Some((_, end)) if *end == i => *end += 1,
+                _ => masked.push((i, i + 1)),
+            }
+        }
+
+        // Encode the sequence (and its complement) once, rather than once per k-mer.
+        // Masked bytes are given an arbitrary value since they never form minimizers.
+        let encoded: Vec<u8> = sequence
+            .iter()
+            .map(|b| alphabet.encode(*b).unwrap_or(0))
+            .collect();
+        let encoded_complement: Option<Vec<u8>> = if canonical {
+            let mut encoded_complement = Vec::with_capacity(sequence.len());
+            for &b in sequence {
+                match (alphabet.encode(complement(b)), alphabet.encode(b)) {
+                    (Some(c), _) => encoded_complement.push(c),
+                    // Masked
+                    (None, None) => encoded_complement.push(0),
+                    (None, Some(_)) => return None,
+                }
+            }
+            Some(encoded_complement)
         } else {
-            alphabet.len().ilog2() + 1
+            None
         };
 
         // Construct a sequence of Kmers
-        let kmers: Vec<_> = sequence
-            .windows(k)
-            .map(|window| {
-                Kmer::Data(IntVec::from_iter(
-                    bits_underlying.try_into().unwrap(),
-                    window
-                        .iter()
-                        // TODO: Change return type to Option<Self> and remove unwrap().
-                        .map(|b| alphabet.0.get_by_left(b).unwrap().clone()),
-                ))
+        let num_kmers = sequence.len() - k + 1;
+        let kmers: Vec<_> = if k * bits <= MAX_PACKED_KMER_BITS {
+            // Each chunk of k-mers starts rolling from scratch, so chunks can be packed in parallel
+            let chunk_starts: Vec<usize> = (0..num_kmers).step_by(PARALLEL_MIN_LEN).collect();
+            chunk_starts
+                .into_par_iter()
+                .flat_map_iter(|start| {
+                    let end = (start + PARALLEL_MIN_LEN).min(num_kmers) + k - 1;
+                    let encoded_complement = encoded_complement.as_ref().map(|c| &c[start..end]);
+                    RollingKmers::new(&encoded[start..end], encoded_complement, k, bits)
+                })
+                .collect()
+        } else {
+            par_map(num_kmers, |i| {
+                let forward = encoded[i..i + k].iter().copied();
+                let kmer = match &encoded_complement {
+                    Some(encoded_complement) => {
+                        let reverse = encoded_complement[i..i + k].iter().rev().copied();
+                        // Element-wise order on the encoded values is the order used by compare_kmers
+                        if forward.clone().le(reverse.clone()) {
+                            IntVec::from_iter(bits, forward)
+                        } else {
+                            IntVec::from_iter(bits, reverse)
+                        }
+                    }
+                    None => IntVec::from_iter(bits, forward),
+                };
+                Kmer::Long(Box::new(kmer))
             })
-            .collect();
+        };
 
-        Self {
+        Some(Self {
             kmers,
             k,
             occ: None,
+            reference: PackedSequence::new(sequence, &alphabet, masked),
             alphabet,
-            original_string: sequence.to_owned(),
+            canonical,
+        })
+    }
+
+    /// Flags every k-mer that overlaps a masked interval.
+    fn masked_kmers(&self) -> Vec<bool> {
+        self.masked_substrings(self.k)
+    }
+
+    /// Flags every substring of length `len` that overlaps a masked interval.
+    fn masked_substrings(&self, len: usize) -> Vec<bool> {
+        let num_substrings = self.reference.len() + 1 - len;
+        let mut masked_substrings = vec![false; num_substrings];
+        for &(start, end) in self.reference.masked_intervals() {
+            let first = start.saturating_sub(len - 1);
+            let last = end.min(num_substrings);
+            masked_substrings[first..last].fill(true);
         }
+        masked_substrings
+    }
+
+    pub fn masked_intervals(&self) -> &[(usize, usize)] {
+        self.reference.masked_intervals()
     }
 
     pub fn generate_occ(&mut self) -> () {
-        let mut occ = HashMap::new();
-        for kmer in self.kmers.iter() {
+        let mut occ = OccurrenceCounts::default();
+        let masked_kmers = self.masked_kmers();
+        for (kmer, _) in self.kmers.iter().zip(masked_kmers).filter(|(_, masked)| !masked) {
             occ.entry(kmer.clone())
                 .and_modify(|v| {*v += 1})
                 .or_insert(0);
@@ -124,12 +629,12 @@ impl KmerSequence {
         self.occ = Some(occ);
     }
 
-    pub fn get_original_string(&self) -> &[u8] {
-        &self.original_string
+    pub fn get_reference(&self) -> &PackedSequence {
+        &self.reference
     }
 
     pub fn get_original_string_len(&self) -> usize {
-        self.original_string.len()
+        self.reference.len()
     }
 
     // Panics if the kmer isn't a part of this KmerSequence
@@ -138,120 +643,295 @@ impl KmerSequence {
             (Kmer::Sentinel, Kmer::Sentinel) => Ordering::Equal,
             (Kmer::Sentinel, _) => Ordering::Greater,
             (_, Kmer::Sentinel) => Ordering::Less,
-            (Kmer::Data(d1), Kmer::Data(d2)) => d1.iter().cmp(d2.iter()),
+            (Kmer::Data(x1), Kmer::Data(x2)) => x1.cmp(x2),
+            (Kmer::Long(d1), Kmer::Long(d2)) => d1.cmp(d2),
+            _ => panic!("compared k-mers of different lengths"),
+        }
+    }
+
+    /// The encoded characters of `kmer`, first to last. Panics on the sentinel.
+    fn kmer_symbols<'a>(&self, kmer: &'a Kmer) -> impl Iterator<Item = u8> + 'a {
+        let bits = self.alphabet.bits();
+        let mask = (1u128 << bits) - 1;
+        match kmer {
+            &Kmer::Data(x) => Either::Left((0..self.k).rev().map(move |j| (x >> (j * bits) & mask) as u8)),
+            Kmer::Long(d) => Either::Right(d.iter()),
+            Kmer::Sentinel => panic!("the sentinel k-mer has no characters"),
+        }
+    }
+
+    /// Returns the position of the minimizer of each window of `w` k-mers under order `o`, or
+    /// None if every k-mer in the window is masked. Returns None if `o` is
+    /// `MinimizerOrder::Occurrence` and an unmasked k-mer doesn't occur in `reference`.
+    ///
+    /// Ties are broken towards the leftmost k-mer. Tied k-mers are equal, and super-k-mers merge
+    /// consecutive windows with equal minimizers, so this doesn't affect the super-k-mers.
+    fn minimizer_positions(
+        &self,
+        w: usize,
+        o: MinimizerOrder,
+        seed: u64,
+        reference: Option<&KmerSequence>,
+    ) -> Option<Vec<Option<usize>>> {
+        let masked_kmers = self.masked_kmers();
+        let compare_at = |i: usize, j: usize| self.compare_kmers(&self.kmers[i], &self.kmers[j]);
+        let positions = match o {
+            MinimizerOrder::Lexicographic => chunked_sliding_window_argmin(&masked_kmers, w, compare_at),
+            MinimizerOrder::Occurrence => {
+                let occ = if let Some(reference) = reference { &reference.occ.as_ref().expect("Reference needs generate_occ() call for occurrence minimizer order") } else { &self.occ.as_ref().expect("Reference needs generate_occ() call for occurrence minimizer order") };
+
+                // Look up each k-mer's count once rather than on every comparison.
+                // Masked k-mers are never compared, so their count is irrelevant.
+                let counts: Vec<usize> = par_map(self.kmers.len(), |i| {
+                    if masked_kmers[i] { Some(0) } else { occ.get(&self.kmers[i]).copied() }
+                })
+                .into_iter()
+                .collect::<Option<_>>()?;
+
+                chunked_sliding_window_argmin(&masked_kmers, w, |i, j| {
+                    counts[i].cmp(&counts[j]).then_with(|| compare_at(i, j))
+                })
+            }
+            MinimizerOrder::Hash => {
+                // Masked k-mers are never compared, so their hash is irrelevant
+                let hashes: Vec<u64> = par_map(self.kmers.len(), |i| {
+                    if masked_kmers[i] { 0 } else { self.kmer_hash(&self.kmers[i], seed) }
+                });
+
+                // K-mers with colliding hashes are ordered lexicographically
+                chunked_sliding_window_argmin(&masked_kmers, w, |i, j| {
+                    hashes[i].cmp(&hashes[j]).then_with(|| compare_at(i, j))
+                })
+            }
+            MinimizerOrder::ModMinimizer => self.mod_minimizer_positions(w, seed, &masked_kmers),
+            MinimizerOrder::Decycling => {
+                // Masked k-mers are never compared, so their rank is irrelevant
+                let ranks: Vec<(u8, u64)> = par_map(self.kmers.len(), |i| {
+                    let kmer = &self.kmers[i];
+                    if masked_kmers[i] { (2, 0) } else { (self.decycling_rank(kmer), self.kmer_hash(kmer, seed)) }
+                });
+
+                chunked_sliding_window_argmin(&masked_kmers, w, |i, j| {
+                    ranks[i].cmp(&ranks[j]).then_with(|| compare_at(i, j))
+                })
+            }
+        };
+        Some(positions)
+    }
+
+    /// Returns the minimizer of each window of `w` k-mers under order `o` (see
+    /// `minimizer_positions`).
+    fn minimizer_chain(&self, w: usize, o: MinimizerOrder, seed: u64, reference: Option<&KmerSequence>) -> Option<Vec<Option<&Kmer>>> {
+        let positions = self.minimizer_positions(w, o, seed, reference)?;
+        Some(positions.into_iter().map(|i| i.map(|i| &self.kmers[i])).collect())
+    }
+
+    /// Whether the leftmost smallest s-mer of `kmer` starts at one of `params.offsets`.
+    fn is_syncmer(&self, kmer: &Kmer, params: &SyncmerParams) -> bool {
+        if *kmer == Kmer::Sentinel {
+            return false;
         }
+        let symbols: Vec<u8> = self.kmer_symbols(kmer).collect();
+        // k is small, so comparing every s-mer of the k-mer is cheap
+        let smallest = (0..=symbols.len() - params.s)
+            .min_by_key(|&i| &symbols[i..i + params.s])
+            .unwrap();
+        params.offsets.contains(&smallest)
     }
 
-    // TODO: switch from the naive approach to something more efficient
-    pub fn compute_minimizer_chain(&self, w: usize) -> Vec<&Kmer> {
-        assert!(self.kmers.len() >= w);
-        assert!(w >= 1);
+    /// Samples every unmasked syncmer as a super-k-mer spanning just that k-mer.
+    /// Consecutive syncmers may be separated by gaps of unsampled k-mers.
+    pub fn compute_syncmers(&self, params: &SyncmerParams) -> Vec<SuperKmer> {
+        assert!(1 <= params.s && params.s <= self.k);
+        assert!(params.offsets.iter().all(|&offset| offset <= self.k - params.s));
 
-        // Find the minimizers for each k-mer window
+        let masked_kmers = self.masked_kmers();
+        let is_syncmer = par_map(self.kmers.len(), |i| !masked_kmers[i] && self.is_syncmer(&self.kmers[i], params));
         self.kmers
-            .windows(w)
-            .map(|window| {
-                // The minimizer kmer in this window
-                window
-                    .iter()
-                    .min_by(|&kmer1, &kmer2| self.compare_kmers(kmer1, kmer2))
-                    .unwrap()
+            .iter()
+            .zip(is_syncmer)
+            .enumerate()
+            .filter(|&(_, (_, is_syncmer))| is_syncmer)
+            .map(|(i, (kmer, _))| SuperKmer {
+                start_pos: i,
+                length: self.k,
+                minimizer: kmer.clone(),
             })
             .collect()
     }
 
-    pub fn compute_minimizer_chain2(&self, w: usize, reference: Option<&KmerSequence>) -> Option<Vec<&Kmer>> {
-        assert!(self.kmers.len() >= w);
-        assert!(w >= 1);
+    /// Returns the position of the mod-minimizer of each window of `w` k-mers, or None if no
+    /// t-mer of the window or the selected k-mer is masked. Not strand-independent for
+    /// canonical k-mers.
+    fn mod_minimizer_positions(&self, w: usize, seed: u64, masked_kmers: &[bool]) -> Vec<Option<usize>> {
+        assert!(!self.canonical, "mod-minimizers of canonical k-mers depend on the strand");
+        let k = self.k;
+        let t = if k <= MOD_MINIMIZER_R { k } else { MOD_MINIMIZER_R + (k - MOD_MINIMIZER_R) % w };
 
-        let occ = if let Some(reference) = reference { &reference.occ.as_ref().expect("Reference needs generate_occ() call for occurrence minimizer order") } else { &self.occ.as_ref().expect("Reference needs generate_occ() call for occurrence minimizer order") };
+        // Hash every t-mer of the encoded string
+        let alphabet_size = self.alphabet.len().max(2) as u128;
+        let encoded: Vec<u128> = self
+            .reference
+            .decode(0..self.reference.len())
+            .iter()
+            .map(|&b| self.alphabet.encode(b).unwrap_or(0) as u128)
+            .collect();
+        let hashes: Vec<u64> = par_map(encoded.len() + 1 - t, |i| {
+            hash_integer(encoded[i..i + t].iter().fold(0, |acc, &x| acc * alphabet_size + x), seed)
+        });
 
-        if let Some(_) = reference {
-            let all_kmers_present = self.kmers.iter().fold(true, |acc, x| acc && if occ.get(x) == None { false } else { true });
-            if !all_kmers_present {
-                return None;
-            }
+        let tmer_minima = chunked_sliding_window_argmin(&self.masked_substrings(t), w + k - t, |i, j| {
+            hashes[i].cmp(&hashes[j]).then_with(|| encoded[i..i + t].cmp(&encoded[j..j + t]))
+        });
+        tmer_minima
+            .into_iter()
+            .enumerate()
+            .map(|(window, x)| {
+                let i = window + (x? - window) % w;
+                (!masked_kmers[i]).then_some(i)
+            })
+            .collect()
+    }
+
+    /// Ranks `kmer` by the double decycling order: 0 if it is in Mykkeltveit's minimum
+    /// decycling set, 1 if it is in the mirrored set, and 2 otherwise. Embedding the k-mer x as
+    /// sum_j x_j e^(2 pi i j / k) in the complex plane, the decycling set holds the k-mers in
+    /// the upper half-plane whose left rotation isn't, so every cycle of rotations with a
+    /// non-zero embedding contains one. The mirrored set swaps the half-planes.
+    fn decycling_rank(&self, kmer: &Kmer) -> u8 {
+        if *kmer == Kmer::Sentinel {
+            return 2;
+        }
+        let k = self.k as f64;
+        let (mut im, mut im_rotated) = (0.0, 0.0);
+        for (j, x) in self.kmer_symbols(kmer).enumerate() {
+            let angle = 2.0 * std::f64::consts::PI * j as f64 / k;
+            im += x as f64 * angle.sin();
+            im_rotated += x as f64 * (angle - 2.0 * std::f64::consts::PI / k).sin();
         }
 
-        Some(self.kmers
-            .windows(w)
-            .map(|window| {
-                window
-                    .iter()
-                    .min_by(|&kmer1, &kmer2| {
-                        let result = occ.get(kmer1).unwrap().cmp(occ.get(kmer2).unwrap());
-                        if result == Ordering::Equal {
-                            self.compare_kmers(kmer1, kmer2)
-                        } else {
-                            result
-                        }
-                    })
-                    .unwrap()
-            })
-            .collect())
+        // Tolerate rounding error in embeddings that lie on the real axis
+        const EPSILON: f64 = 1e-9;
+        if im > EPSILON && im_rotated <= EPSILON {
+            0
+        } else if im < -EPSILON && im_rotated >= -EPSILON {
+            1
+        } else {
+            2
+        }
     }
 
-    pub fn compute_super_kmers(&self, w: usize, o: MinimizerOrder, reference: Option<&KmerSequence>) -> Option<Vec<SuperKmer>> {
+    /// `seed` is only used by the hash-based orders.
+    pub fn compute_super_kmers(&self, w: usize, o: MinimizerOrder, seed: u64, reference: Option<&KmerSequence>) -> Option<Vec<SuperKmer>> {
+        self.compute_super_kmers_counting_positions(w, o, seed, reference).map(|(super_kmers, _)| super_kmers)
+    }
+
+    /// Like `compute_super_kmers`, but also returns how many super-k-mers merging windows with
+    /// the same minimizer position, rather than value, would give. The two only differ where
+    /// windows choose equal k-mers at different positions, e.g. in homopolymers and tandem
+    /// repeats.
+    pub fn compute_super_kmers_counting_positions(
+        &self,
+        w: usize,
+        o: MinimizerOrder,
+        seed: u64,
+        reference: Option<&KmerSequence>,
+    ) -> Option<(Vec<SuperKmer>, usize)> {
         // Compute the minimizer chain
-        let mut minimizers = match o {
-            MinimizerOrder::Lexicographic => self.compute_minimizer_chain(w).into_iter().enumerate(),
-            MinimizerOrder::Occurrence => {
-                let min_chain = self.compute_minimizer_chain2(w, reference)?;
-                min_chain.into_iter().enumerate()
-            },
-        };
+        let minimizers = self.minimizer_chain(w, o, seed, reference)?;
+        let num_windows = minimizers.len();
 
-        // De-duplication (taking the first start position and accumulating lengths)
+        // The minimizers are references into `self.kmers`, so equal addresses are equal positions
+        let num_positions = minimizers
+            .iter()
+            .dedup_by(|&&m1, &&m2| m1.zip(m2).is_some_and(|(m1, m2)| std::ptr::eq(m1, m2)))
+            .filter(|m| m.is_some())
+            .count();
+
+        // De-duplication (taking the first start position and accumulating lengths).
+        // Windows without a minimizer end the current super-k-mer.
         let mut super_kmers: Vec<SuperKmer> = Vec::new();
-        let (mut curr_start_i, mut curr_minimizer) = minimizers.next().unwrap();
-        let mut curr_count = 1;
-        for (i, minimizer) in minimizers {
-            if self.compare_kmers(minimizer, curr_minimizer) == Ordering::Equal {
-                // deduplicate current minimizer
-                curr_count += 1;
-            } else {
+        let mut curr: Option<(usize, &Kmer)> = None;
+        for (i, minimizer) in minimizers.into_iter().enumerate() {
+            if let Some((curr_start_i, curr_minimizer)) = curr {
+                if minimizer.is_some_and(|m| self.compare_kmers(m, curr_minimizer) == Ordering::Equal) {
+                    // deduplicate current minimizer
+                    continue;
+                }
+
                 // Add the "previous" minimizer, de-duplicated
                 super_kmers.push(SuperKmer {
                     start_pos: curr_start_i,
-                    length: curr_count + (w + self.k - 1) - 1,
+                    length: (i - curr_start_i) + (w + self.k - 1) - 1,
                     minimizer: curr_minimizer.clone(),
                 });
-
-                // reset the current element
-                (curr_start_i, curr_minimizer) = (i, minimizer);
-                curr_count = 1;
             }
+
+            // reset the current element
+            curr = minimizer.map(|m| (i, m));
         }
         // Special case: add the last element
-        super_kmers.push(SuperKmer {
-            start_pos: curr_start_i,
-            length: curr_count + (w + self.k - 1) - 1,
-            minimizer: curr_minimizer.clone(),
-        });
+        if let Some((curr_start_i, curr_minimizer)) = curr {
+            super_kmers.push(SuperKmer {
+                start_pos: curr_start_i,
+                length: (num_windows - curr_start_i) + (w + self.k - 1) - 1,
+                minimizer: curr_minimizer.clone(),
+            });
+        }
 
-        Some(super_kmers)
+        Some((super_kmers, num_positions))
     }
 
     pub fn alphabet(&self) -> Alphabet {
         self.alphabet.clone()
     }
 
+    pub fn get_alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
     pub fn k(&self) -> usize {
         self.k
     }
 
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// Whether every k-mer of this sequence can be represented by `kmer_to_integer`,
+    /// i.e. whether alphabet size ^ k fits in a u128.
+    pub fn kmers_fit_in_u128(&self) -> bool {
+        self.alphabet.kmers_fit_in_u128(self.k)
+    }
+
+    /// Panics if the k-mer doesn't fit in a u128 (see `kmers_fit_in_u128`).
     pub fn kmer_to_integer(&self, kmer: &Kmer) -> u128 {
+        let alphabet_size = self.alphabet.len();
         match kmer {
-            Kmer::Data(d) => d.as_u128(self.alphabet.len()),
+            // Packed k-mers are already in base alphabet size if it's a power of two
+            &Kmer::Data(x) if alphabet_size == 1 << self.alphabet.bits() => x,
+            // A packed k-mer takes at least as many bits as its value in base alphabet size
+            Kmer::Data(_) => self
+                .kmer_symbols(kmer)
+                .fold(0, |acc, x| acc * alphabet_size as u128 + x as u128),
+            Kmer::Long(d) => d
+                .as_u128(alphabet_size)
+                .expect("k-mer is too long to be represented as a u128 for this alphabet size"),
             Kmer::Sentinel => panic!("called Kmer::to_integer() on sentinel k-kmer"),
         }
     }
+
+    /// A seeded 64-bit hash of `kmer_to_integer`, used by `MinimizerOrder::Hash`.
+    pub fn kmer_hash(&self, kmer: &Kmer, seed: u64) -> u64 {
+        hash_integer(self.kmer_to_integer(kmer), seed)
+    }
 }
 
 impl fmt::Debug for Kmer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Kmer::Data(d) => write!(f, "Kmer [{}]", d.iter().format(", ")),
+            Kmer::Data(x) => write!(f, "Kmer {:#x}", x),
+            Kmer::Long(d) => write!(f, "Kmer [{}]", d.iter().format(", ")),
             Kmer::Sentinel => write!(f, "Kmer $"),
         }
     }
@@ -262,7 +942,7 @@ impl fmt::Debug for Alphabet {
         write!(
             f,
             "Alphabet [{}]",
-            self.0
+            self.map
                 .iter()
                 .map(|(&from, &to)| format!("{} <> {}", from as char, to))
                 .format(", ")
@@ -280,25 +960,290 @@ mod tests {
     fn kmer_u128_ordering() {
         let mut rng = rand::rng();
 
-        for k in 1..10 {
-            let base_str = "ACTGACTGACTGACTGACTGACTGACTGACTG".as_bytes();
-            let alphabet = Alphabet::from_bytes(base_str);
-            let base_kmers = KmerSequence::from_bytes(base_str, k, alphabet);
+        // Packed k-mers over alphabets of power-of-two and other sizes, and k-mers too long to pack
+        for (letters, ks) in [("ACTG", 1..10), ("ACG", 1..10), ("ACG", 65..70)] {
+            let letters = letters.as_bytes();
+            let alphabet = Alphabet::from_bytes(letters);
+            let mut random_kmer = |k| {
+                let sequence: Vec<u8> = (0..k).map(|_| letters[rng.random_range(0..letters.len())]).collect();
+                KmerSequence::from_bytes(&sequence, k, alphabet.clone()).unwrap().kmers[0].clone()
+            };
+
+            for k in ks {
+                let base_kmers = KmerSequence::from_bytes(&vec![letters[0]; k], k, alphabet.clone()).unwrap();
+                for _ in 0..100000 {
+                    let kmer1 = random_kmer(k);
+                    let kmer2 = random_kmer(k);
+
+                    let ord1 = base_kmers.compare_kmers(&kmer1, &kmer2);
+                    let ord2 = base_kmers
+                        .kmer_to_integer(&kmer1)
+                        .cmp(&base_kmers.kmer_to_integer(&kmer2));
+
+                    assert_eq!(ord1, ord2);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rolling_kmers_match_windows() {
+        let mut rng = rand::rng();
+        let sequence: Vec<u8> = (0..3000).map(|_| b"ACGTN"[rng.random_range(0..5)]).collect();
+        let alphabet = Alphabet::dna();
+        let encode = |window: &[u8]| -> Vec<u8> { window.iter().map(|&b| alphabet.encode(b).unwrap_or(0)).collect() };
+
+        for k in [1, 2, 7, 31, 63, 64, 65, 100] {
+            for canonical in [false, true] {
+                let kmers = KmerSequence::from_bytes_with(&sequence, k, alphabet.clone(), canonical).unwrap();
+                assert_eq!(kmers.kmers.len(), sequence.len() - k + 1);
+                for (kmer, window) in kmers.kmers.iter().zip(sequence.windows(k)) {
+                    let mut expected = encode(window);
+                    if canonical {
+                        expected = expected.min(encode(&reverse_complement(window)));
+                    }
+                    assert_eq!(kmers.kmer_symbols(kmer).collect::<Vec<_>>(), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_minimizers_are_strand_independent() {
+        let sequence = "ACTGACCCGTAGCGCTAACTGGTCAGGCTAGCATTTAGC".as_bytes();
+        let sequence_rc = reverse_complement(sequence);
+        let alphabet = Alphabet::from_bytes(sequence).complement_closed();
+
+        for k in 1..8 {
+            for w in 1..8 {
+                let forward = KmerSequence::from_bytes_canonical(sequence, k, alphabet.clone()).unwrap();
+                let reverse = KmerSequence::from_bytes_canonical(&sequence_rc, k, alphabet.clone()).unwrap();
+
+                let mut reverse_chain = reverse.minimizer_chain(w, MinimizerOrder::Lexicographic, 0, None).unwrap();
+                reverse_chain.reverse();
+                assert_eq!(forward.minimizer_chain(w, MinimizerOrder::Lexicographic, 0, None).unwrap(), reverse_chain);
+            }
+        }
+    }
+
+    #[test]
+    fn dna_alphabet_masks_non_acgt() {
+        let sequence = "ACGTNNNNacgtRYACGGTNA".as_bytes();
+        let alphabet = Alphabet::dna();
+        assert_eq!(alphabet.len(), 4);
+        assert_eq!(alphabet.encode(b'g'), alphabet.encode(b'G'));
+        assert_eq!(alphabet.encode(b'N'), None);
+
+        let k = 3;
+        let kmers = KmerSequence::from_bytes(sequence, k, alphabet).unwrap();
+        assert_eq!(kmers.masked_intervals(), &[(4, 8), (12, 14), (19, 20)]);
+        assert!(Alphabet::from_bytes(b"ACGT").encode(b'a').is_none());
+
+        for w in 1..6 {
+            for o in [MinimizerOrder::Lexicographic, MinimizerOrder::Occurrence, MinimizerOrder::Hash, MinimizerOrder::ModMinimizer, MinimizerOrder::Decycling] {
+                let mut kmers = KmerSequence::from_bytes(sequence, k, Alphabet::dna()).unwrap();
+                kmers.generate_occ();
+                for super_kmer in kmers.compute_super_kmers(w, o, 0, None).unwrap() {
+                    // The minimizer must come from an unmasked k-mer inside the super-k-mer
+                    assert!(super_kmer.minimizer != Kmer::Sentinel);
+                    let found = (super_kmer.start_pos..super_kmer.start_pos + super_kmer.length + 1 - k)
+                        .filter(|&i| !kmers.masked_intervals().iter().any(|&(s, e)| i < e && s < i + k))
+                        .any(|i| kmers.kmers[i] == super_kmer.minimizer);
+                    assert!(found, "{:?}", super_kmer);
+                    for &(start, end) in kmers.masked_intervals() {
+                        // No super-k-mer spans a fully masked window
+                        assert!(!(super_kmer.start_pos <= start && end + k <= super_kmer.start_pos + super_kmer.length && end - start >= k + w - 1));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn protein_alphabets() {
+        let alphabet = Alphabet::protein(None);
+        assert_eq!(alphabet.len(), 20);
+        assert_eq!(alphabet.bits(), 5);
+        assert_eq!(alphabet.encode(b'w'), alphabet.encode(b'W'));
+        assert_eq!(alphabet.encode(b'X'), None);
+
+        let alphabet = Alphabet::protein(Some(AlphabetReduction::Murphy10));
+        assert_eq!(alphabet.len(), 10);
+        assert_eq!(alphabet.bits(), 4);
+        assert_eq!(alphabet.encode(b'L'), alphabet.encode(b'v'));
+        assert_ne!(alphabet.encode(b'L'), alphabet.encode(b'C'));
+
+        let alphabet = Alphabet::protein(Some(AlphabetReduction::Dayhoff6));
+        assert_eq!(alphabet.len(), 6);
+        assert_eq!(alphabet.bits(), 3);
+        assert_eq!(alphabet.encode(b'A'), alphabet.encode(b'T'));
+
+        // Every reduced group covers the standard amino acids exactly once
+        for reduction in [AlphabetReduction::Murphy10, AlphabetReduction::Dayhoff6] {
+            let mut residues: Vec<u8> = reduction.groups().concat();
+            residues.sort();
+            assert_eq!(residues, AMINO_ACIDS);
+        }
+
+        assert!(Alphabet::protein(None).kmers_fit_in_u128(29));
+        assert!(!Alphabet::protein(None).kmers_fit_in_u128(30));
+        assert_eq!(Alphabet::from_bytes(b"AAAA").bits(), 1);
+    }
+
+    /// The leftmost smallest unmasked k-mer of each window, by a full scan of the window
+    fn naive_minimizer_chain<'a>(
+        kmers: &'a KmerSequence,
+        w: usize,
+        occ: Option<&OccurrenceCounts>,
+    ) -> Vec<Option<&'a Kmer>> {
+        let masked_kmers = kmers.masked_kmers();
+        (0..=kmers.kmers.len() - w)
+            .map(|start| {
+                (start..start + w)
+                    .filter(|&i| !masked_kmers[i])
+                    .map(|i| &kmers.kmers[i])
+                    .min_by(|&kmer1, &kmer2| match occ {
+                        Some(occ) => occ[kmer1].cmp(&occ[kmer2]).then_with(|| kmers.compare_kmers(kmer1, kmer2)),
+                        None => kmers.compare_kmers(kmer1, kmer2),
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn minimizer_chain_matches_naive() {
+        let mut rng = rand::rng();
+
+        for _ in 0..50 {
+            let len = rng.random_range(1..200);
+            let sequence: Vec<u8> = (0..len).map(|_| b"ACGTN"[rng.random_range(0..5)]).collect();
+            for k in 1..6.min(len + 1) {
+                let mut kmers = KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap();
+                kmers.generate_occ();
+                for w in 1..12.min(kmers.kmers.len() + 1) {
+                    let hashes: OccurrenceCounts =
+                        kmers.kmers.iter().map(|kmer| (kmer.clone(), kmers.kmer_hash(kmer, 7) as usize)).collect();
+                    for (o, occ) in [
+                        (MinimizerOrder::Lexicographic, None),
+                        (MinimizerOrder::Occurrence, kmers.occ.as_ref()),
+                        (MinimizerOrder::Hash, Some(&hashes)),
+                    ] {
+                        let expected = naive_minimizer_chain(&kmers, w, occ);
+
+                        let chain = kmers.minimizer_chain(w, o, 7, None).unwrap();
+                        assert_eq!(chain.len(), expected.len());
+                        for (minimizer, expected) in chain.iter().zip(&expected) {
+                            assert_eq!(minimizer.map(|m| m as *const Kmer), expected.map(|m| m as *const Kmer));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tied_minimizers_share_a_super_kmer() {
+        // Every window's leftmost minimizer is at a different position, but all are equal
+        let kmers = KmerSequence::from_bytes(b"AAAAAAAAAAAAAAAAAAAAAAAAA", 2, Alphabet::dna()).unwrap();
+        let positions = kmers.minimizer_positions(4, MinimizerOrder::Lexicographic, 0, None).unwrap();
+        assert!(positions.iter().enumerate().all(|(i, &position)| position == Some(i)));
+        let (super_kmers, num_positions) =
+            kmers.compute_super_kmers_counting_positions(4, MinimizerOrder::Lexicographic, 0, None).unwrap();
+        assert_eq!(super_kmers.len(), 1);
+        assert_eq!(num_positions, positions.len());
+
+        // Masked windows split both counts
+        let kmers = KmerSequence::from_bytes(b"ACGTTGCANACGTTGCA", 3, Alphabet::dna()).unwrap();
+        for w in 1..5 {
+            let positions = kmers.minimizer_positions(w, MinimizerOrder::Lexicographic, 0, None).unwrap();
+            let (super_kmers, num_positions) =
+                kmers.compute_super_kmers_counting_positions(w, MinimizerOrder::Lexicographic, 0, None).unwrap();
+            assert_eq!(num_positions, positions.iter().dedup().flatten().count());
+            assert!(num_positions >= super_kmers.len());
+        }
+    }
+
+    #[test]
+    fn occurrence_minimizers_against_reference() {
+        let mut reference = KmerSequence::from_bytes(b"ACGTTGCAACGTAGGCTAACGT", 3, Alphabet::dna()).unwrap();
+        reference.generate_occ();
 
-            for _ in 0..100000 {
-                let vals1: Vec<u8> = (0..k).map(|_| rng.random_range(0..4)).collect();
-                let vals2: Vec<u8> = (0..k).map(|_| rng.random_range(0..4)).collect();
+        let query = KmerSequence::from_bytes(b"GCAACGTAGG", 3, Alphabet::dna()).unwrap();
+        for w in 1..=query.kmers.len() {
+            let expected: Vec<_> = naive_minimizer_chain(&query, w, reference.occ.as_ref())
+                .into_iter()
+                .map(|m| m.cloned())
+                .collect();
+            let actual: Vec<_> = query
+                .minimizer_chain(w, MinimizerOrder::Occurrence, 0, Some(&reference))
+                .unwrap()
+                .into_iter()
+                .map(|m| m.cloned())
+                .collect();
+            assert_eq!(actual, expected);
+        }
 
-                let kmer1 = Kmer::Data(IntVec::from_iter(2, vals1.into_iter()));
-                let kmer2 = Kmer::Data(IntVec::from_iter(2, vals2.into_iter()));
+        // A k-mer that the reference lacks has no occurrence rank
+        let query = KmerSequence::from_bytes(b"GCAAAA", 3, Alphabet::dna()).unwrap();
+        assert!(query.minimizer_chain(1, MinimizerOrder::Occurrence, 0, Some(&reference)).is_none());
+    }
 
-                let ord1 = base_kmers.compare_kmers(&kmer1, &kmer2);
-                let ord2 = base_kmers
-                    .kmer_to_integer(&kmer1)
-                    .cmp(&base_kmers.kmer_to_integer(&kmer2));
+    #[test]
+    fn syncmers_are_context_free() {
+        let mut rng = rand::rng();
+
+        for _ in 0..20 {
+            let sequence: Vec<u8> = (0..150).map(|_| b"ACGT"[rng.random_range(0..4)]).collect();
+            for k in 2..8 {
+                for s in 1..=k {
+                    let params = SyncmerParams::closed(k, s);
+                    let kmers = KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap();
+                    let syncmers = kmers.compute_syncmers(&params);
+
+                    // Closed syncmers occur in every k - s consecutive k-mers
+                    if s < k {
+                        let mut prev = None;
+                        for super_kmer in syncmers.iter().chain([&SuperKmer { start_pos: kmers.kmers.len(), length: 0, minimizer: Kmer::Sentinel }]) {
+                            let gap = super_kmer.start_pos - prev.map_or(0, |p| p + 1);
+                            assert!(gap < k - s, "{:?}", String::from_utf8_lossy(&sequence));
+                            prev = Some(super_kmer.start_pos);
+                        }
+                    }
 
-                assert_eq!(ord1, ord2);
+                    // A substring's syncmers are exactly the reference's syncmers inside it
+                    let (start, end) = (37, 90);
+                    let substring = KmerSequence::from_bytes(&sequence[start..end], k, Alphabet::dna()).unwrap();
+                    let expected: Vec<_> = syncmers
+                        .iter()
+                        .filter(|super_kmer| start <= super_kmer.start_pos && super_kmer.start_pos + k <= end)
+                        .map(|super_kmer| (super_kmer.start_pos - start, super_kmer.minimizer.clone()))
+                        .collect();
+                    let actual: Vec<_> = substring
+                        .compute_syncmers(&params)
+                        .into_iter()
+                        .map(|super_kmer| (super_kmer.start_pos, super_kmer.minimizer))
+                        .collect();
+                    assert_eq!(actual, expected);
+                }
             }
         }
     }
+
+    #[test]
+    fn low_density_orders() {
+        let mut rng = rand::rng();
+        let sequence: Vec<u8> = (0..20000).map(|_| b"ACGT"[rng.random_range(0..4)]).collect();
+
+        for (o, k, w) in [(MinimizerOrder::ModMinimizer, 31, 12), (MinimizerOrder::Decycling, 11, 24)] {
+            let kmers = KmerSequence::from_bytes(&sequence, k, Alphabet::dna()).unwrap();
+            let num_super_kmers = |o| kmers.compute_super_kmers(w, o, 0, None).unwrap().len();
+            assert!(num_super_kmers(o) < num_super_kmers(MinimizerOrder::Hash));
+        }
+
+        // Mod-minimizers with w = 1 sample every k-mer
+        let kmers = KmerSequence::from_bytes(&sequence, 31, Alphabet::dna()).unwrap();
+        let chain = kmers.minimizer_chain(1, MinimizerOrder::ModMinimizer, 0, None).unwrap();
+        assert_eq!(chain.len(), kmers.kmers.len());
+        assert!(chain.iter().zip(&kmers.kmers).all(|(m, kmer)| std::ptr::eq(m.unwrap(), kmer)));
+    }
 }
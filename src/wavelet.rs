@@ -0,0 +1,255 @@
+// Succinct sequence structures for the FM-index: bit vectors with constant-time rank (and
+// select from sampled positions), and a wavelet matrix (Claude, Navarro and Ordóñez, "The Wavelet Matrix") over integer sequences.
+
+use bincode::{Decode, Encode};
+
+/// The number of bits between stored cumulative counts in a `RankBitVec`
+const RANK_BLOCK_BITS: usize = 512;
+const WORDS_PER_BLOCK: usize = RANK_BLOCK_BITS / 64;
+/// The number of set bits between stored positions for `RankBitVec::select1`
+const SELECT_SAMPLE_ONES: usize = 64;
+
+/// A bit vector that counts the set bits before any position in constant time.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+pub struct RankBitVec {
+    words: Vec<u64>,
+    /// `blocks[b]` is the number of set bits in the first `b * RANK_BLOCK_BITS` bits
+    blocks: Vec<usize>,
+    /// `select_samples[j]` is the position of the set bit preceded by
+    /// `j * SELECT_SAMPLE_ONES` others, or empty without select support
+    select_samples: Vec<usize>,
+    len: usize,
+}
+
+impl RankBitVec {
+    pub fn from_bits(bits: impl IntoIterator<Item = bool>) -> Self {
+        Self::build(bits, false)
+    }
+
+    /// Like `from_bits`, but also samples the positions of set bits for `select1`.
+    pub fn with_select(bits: impl IntoIterator<Item = bool>) -> Self {
+        Self::build(bits, true)
+    }
+
+    fn build(bits: impl IntoIterator<Item = bool>, select: bool) -> Self {
+        let mut words: Vec<u64> = Vec::new();
+        let mut len = 0;
+        for bit in bits {
+            if len % 64 == 0 {
+                words.push(0);
+            }
+            if bit {
+                words[len / 64] |= 1 << (len % 64);
+            }
+            len += 1;
+        }
+
+        let mut blocks = Vec::with_capacity(words.len() / WORDS_PER_BLOCK + 1);
+        let mut ones = 0;
+        for (i, word) in words.iter().enumerate() {
+            if i % WORDS_PER_BLOCK == 0 {
+                blocks.push(ones);
+            }
+            ones += word.count_ones() as usize;
+        }
+        blocks.push(ones);
+
+        let mut select_samples = Vec::new();
+        if select {
+            let mut ones: usize = 0;
+            for (i, &word) in words.iter().enumerate() {
+                let count = word.count_ones() as usize;
+                // The next sample falls in this word if it's within its set bits
+                let mut next = ones.next_multiple_of(SELECT_SAMPLE_ONES);
+                while next < ones + count {
+                    select_samples.push(i * 64 + select_in_word(word, next - ones));
+                    next += SELECT_SAMPLE_ONES;
+                }
+                ones += count;
+            }
+        }
+
+        Self { words, blocks, select_samples, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        assert!(i < self.len);
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    /// The number of set bits in the first `i` bits.
+    pub fn rank1(&self, i: usize) -> usize {
+        assert!(i <= self.len);
+        let word = i / 64;
+        let block = i / RANK_BLOCK_BITS;
+        let mut ones = self.blocks[block];
+        for w in &self.words[block * WORDS_PER_BLOCK..word] {
+            ones += w.count_ones() as usize;
+        }
+        let offset = i % 64;
+        if offset > 0 {
+            ones += (self.words[word] & ((1 << offset) - 1)).count_ones() as usize;
+        }
+        ones
+    }
+
+    /// The position of the set bit preceded by `k` other set bits, found by scanning forward
+    /// from the nearest sampled position. Requires a vector built `with_select`.
+    pub fn select1(&self, k: usize) -> usize {
+        assert!(k < self.blocks[self.blocks.len() - 1], "select1 beyond the last set bit");
+        let sample = self.select_samples[k / SELECT_SAMPLE_ONES];
+        let mut remaining = k % SELECT_SAMPLE_ONES;
+        // Drop the set bits before the sampled one
+        let mut word = self.words[sample / 64] & (u64::MAX << (sample % 64));
+        let mut i = sample / 64;
+        loop {
+            let ones = word.count_ones() as usize;
+            if remaining < ones {
+                return i * 64 + select_in_word(word, remaining);
+            }
+            remaining -= ones;
+            i += 1;
+            word = self.words[i];
+        }
+    }
+
+    /// The number of unset bits in the first `i` bits.
+    pub fn rank0(&self, i: usize) -> usize {
+        i - self.rank1(i)
+    }
+}
+
+/// The position of the set bit of `word` preceded by `k` other set bits.
+fn select_in_word(mut word: u64, mut k: usize) -> usize {
+    let mut offset = 0;
+    // Skip whole bytes, then clear the lowest set bits of the remaining one
+    loop {
+        let ones = (word & 0xff).count_ones() as usize;
+        if k < ones {
+            break;
+        }
+        k -= ones;
+        word >>= 8;
+        offset += 8;
+    }
+    for _ in 0..k {
+        word &= word - 1;
+    }
+    offset + word.trailing_zeros() as usize
+}
+
+/// A sequence of integers below `1 << levels.len()`, supporting access and rank in
+/// O(log sigma) time while taking about log sigma bits per element. Level `l` stores
+/// bit `l` (from the most significant) of each element, with the elements stably
+/// partitioned by their higher bits, zeros first.
+#[derive(Debug, Encode, Decode)]
+pub struct WaveletMatrix {
+    levels: Vec<RankBitVec>,
+    /// `zeros[l]` is the number of unset bits in `levels[l]`
+    zeros: Vec<usize>,
+}
+
+impl WaveletMatrix {
+    pub fn new(values: &[usize], alphabet_size: usize) -> Self {
+        assert!(values.iter().all(|&c| c < alphabet_size));
+        let num_levels = (usize::BITS - (alphabet_size.max(2) - 1).leading_zeros()) as usize;
+
+        let mut levels = Vec::with_capacity(num_levels);
+        let mut zeros = Vec::with_capacity(num_levels);
+        let mut current = values.to_vec();
+        for l in 0..num_levels {
+            let shift = num_levels - 1 - l;
+            let bits = RankBitVec::from_bits(current.iter().map(|&c| c >> shift & 1 == 1));
+            zeros.push(bits.rank0(bits.len()));
+            levels.push(bits);
+
+            let (mut next, ones): (Vec<usize>, Vec<usize>) = current.iter().partition(|&&c| c >> shift & 1 == 0);
+            next.extend(ones);
+            current = next;
+        }
+
+        Self { levels, zeros }
+    }
+
+    /// Returns the element at `i` and its number of occurrences among the first `i` elements,
+    /// in a single traversal.
+    pub fn get_and_rank(&self, mut i: usize) -> (usize, usize) {
+        let (mut c, mut start) = (0, 0);
+        for (bits, &zeros) in self.levels.iter().zip(&self.zeros) {
+            if bits.get(i) {
+                c = c << 1 | 1;
+                start = zeros + bits.rank1(start);
+                i = zeros + bits.rank1(i);
+            } else {
+                c <<= 1;
+                start = bits.rank0(start);
+                i = bits.rank0(i);
+            }
+        }
+        (c, i - start)
+    }
+
+    /// The number of occurrences of `c` among the first `i` elements.
+    pub fn rank(&self, c: usize, i: usize) -> usize {
+        let num_levels = self.levels.len();
+        let (mut start, mut end) = (0, i);
+        for (l, (bits, &zeros)) in self.levels.iter().zip(&self.zeros).enumerate() {
+            if c >> (num_levels - 1 - l) & 1 == 1 {
+                start = zeros + bits.rank1(start);
+                end = zeros + bits.rank1(end);
+            } else {
+                start = bits.rank0(start);
+                end = bits.rank0(end);
+            }
+        }
+        end - start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn rank_bit_vec_matches_naive() {
+        let mut rng = rand::rng();
+        for len in [0, 1, 63, 64, 65, 511, 512, 513, 2000, 5000] {
+            let bits: Vec<bool> = (0..len).map(|_| rng.random_bool(0.3)).collect();
+            let rank_bits = RankBitVec::with_select(bits.iter().copied());
+            assert_eq!(rank_bits.len(), len);
+            for i in 0..=len {
+                assert_eq!(rank_bits.rank1(i), bits[..i].iter().filter(|&&b| b).count());
+            }
+            for (k, i) in bits.iter().positions(|&b| b).enumerate() {
+                assert_eq!(rank_bits.select1(k), i);
+            }
+            for (i, &bit) in bits.iter().enumerate() {
+                assert_eq!(rank_bits.get(i), bit);
+            }
+        }
+    }
+
+    #[test]
+    fn wavelet_matrix_matches_naive() {
+        let mut rng = rand::rng();
+        for alphabet_size in [1, 2, 3, 5, 16, 100] {
+            let values: Vec<usize> = (0..1000).map(|_| rng.random_range(0..alphabet_size)).collect();
+            let matrix = WaveletMatrix::new(&values, alphabet_size);
+            for (i, &c) in values.iter().enumerate() {
+                assert_eq!(matrix.get_and_rank(i), (c, values[..i].iter().filter(|&&x| x == c).count()));
+            }
+            for c in 0..alphabet_size {
+                for i in (0..=values.len()).step_by(37) {
+                    assert_eq!(matrix.rank(c, i), values[..i].iter().filter(|&&x| x == c).count());
+                }
+            }
+        }
+    }
+}